@@ -1,17 +1,13 @@
-use std::collections::HashMap;
-#[feature(rt)]
 use std::time::Duration;
 //use std::fmt;
-use tokio::{runtime, sync::oneshot};
-use tokio::sync::oneshot::{Receiver,Sender};
 use tokio::net::UdpSocket;
+use tracing::{debug, info, trace};
 
 #[derive(Debug)]
 struct Protocolo {
   sock: UdpSocket,
   buffer: Vec<u8>,
   seqno: u16,
-  finished: bool,
   timeout: u16,
   retries: u16,
   estado: Estado
@@ -42,9 +38,8 @@ impl Protocolo {
       sock: UdpSocket::bind(addr).await.expect("ao criar socket UDP"),
       buffer: vec![],
       seqno: 1,
-      finished: false,
-      timeout: timeout,
-      retries: retries,
+      timeout,
+      retries,
       estado: Estado::RX
     }
   }
@@ -58,13 +53,13 @@ impl Protocolo {
     let f_timeout = tokio::time::sleep(Duration::from_secs(self.timeout as u64));
 
     tokio::select! {
-      val = f_timeout, if self.timeout > 0 => {
-        println!("Timeout: val={:?}", val);
+      _ = f_timeout, if self.timeout > 0 => {
+        debug!("Timeout");
         return Evento::Timeout;
       }
       val = self.sock.recv_from(&mut buf) => {
         if let Ok((len,addr)) = val {
-          println!("Rx: val={:?}", (len,addr));
+          debug!(len, %addr, "Rx");
           let msg = buf[..len].to_vec();
           return Evento::Msg(msg);
         }
@@ -89,45 +84,45 @@ impl Protocolo {
   }
 
   async fn handle_rx(&mut self, ev: Evento) {
-    println!("rx");
+    trace!("rx");
 
     match ev {
         Evento::Timeout => {
           self.seqno += 1;
-          println!("Timeout {}", self.seqno);        
+          debug!(seqno = self.seqno, "Timeout");
         }
         Evento::Msg(msg) => {
-          println!("Adicionando {} bytes ao buffer", msg.len());
+          debug!(bytes = msg.len(), "Adicionando ao buffer");
           self.buffer.extend_from_slice(&msg);
           self.seqno = 1;
           // tokio::spawn(async {handle_tx(proto, chan).await;});
           self.estado = Estado::TX;
         }
         _ => {
-          println!("Alguma outra coisa ...");
+          trace!("Alguma outra coisa ...");
         }
     }    
-    println!("handle_rx: terminou");
+    trace!("handle_rx: terminou");
   }
 
   async fn handle_tx(&mut self, ev: Evento) {
-      println!("tx");
+      trace!("tx");
       match ev {
           Evento::Timeout => {
             self.seqno += 1;
             if self.seqno == self.retries {
               self.estado = Estado::Finish;
             }
-            println!("Timeout {}", self.seqno);                
+            debug!(seqno = self.seqno, "Timeout");
           }
           Evento::Msg(msg) => {
-            println!("Adicionando {} bytes ao buffer", msg.len());
+            debug!(bytes = msg.len(), "Adicionando ao buffer");
             self.buffer.extend_from_slice(&msg);
             self.estado = Estado::RX;
             self.seqno = 1;
           }
           _ => {
-            println!("Alguma outra coisa ...");
+            trace!("Alguma outra coisa ...");
           }
       }
   }        
@@ -160,14 +155,15 @@ async fn run_proto() -> Option<Protocolo> {
 // }
 
 fn main() {
-    let mut rt = tokio::runtime::Runtime::new().expect("");
-    // let mut rt = tokio::runtime::Builder::new_multi_thread()
+    let rt = tokio::runtime::Runtime::new().expect("");
+    // let rt = tokio::runtime::Builder::new_multi_thread()
     // .worker_threads(1)
     // .enable_all()
     // .build()
     // .expect("Não conseguiu iniciar runtime !");
 
-    println!("Started task!");
+    tftp::inicia_logs("trace", false);
+    info!("Started task!");
     let r = rt.block_on(run_proto());
-    println!("Stopped task: {:?}", r);
+    info!(proto = ?r, "Stopped task");
 }
@@ -1,33 +1,219 @@
 use std::time::Duration;
+use tokio::time::Instant;
 use tokio::net::UdpSocket;
-use std::fs;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::io;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::collections::{HashSet, VecDeque};
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
 use bytes::BytesMut;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-mod msg;
+use tracing::{debug, info, trace, warn, Instrument, Span};
+use tracing::field::{display, Empty};
+use tracing_subscriber::EnvFilter;
+use std::net::SocketAddr;
+pub mod msg;
+pub use msg::ErrorCode;
+mod netascii;
 
 use msg::Codec;
 
+/// Why a transfer failed
 #[derive(Debug)]
-pub enum Status {
-  OK,
+pub enum TftpError {
+  /// the server's name or address couldn't be resolved
+  Resolution(String),
+  /// a local operation failed: reading or writing the contents, or using the socket.
+  /// If the transfer had started, the peer was sent an ERR
+  Io(io::Error),
+  /// the peer sent a malformed or unexpected message. It was sent an ERR
+  Protocol(String),
+  /// the peer sent an ERR: its code and message
+  Peer(ErrorCode, String),
+  /// the peer stopped answering, even after the retransmissions
   Timeout,
-  MaxRetriesExceeded,
-  Error(u16),
-  Unknown
+  /// the options acknowledged by the server couldn't be accepted (RFC 2347).
+  /// It was sent an ERR
+  Negotiation(String)
+}
+
+impl fmt::Display for TftpError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TftpError::Resolution(server) => write!(f, "could not resolve {}", server),
+      TftpError::Io(e) => write!(f, "local I/O error: {}", e),
+      TftpError::Protocol(text) => write!(f, "protocol violation: {}", text),
+      TftpError::Peer(code, text) => write!(f, "error {} from peer: {}", u16::from(*code), text),
+      TftpError::Timeout => write!(f, "peer not answering"),
+      TftpError::Negotiation(text) => write!(f, "option negotiation failed: {}", text)
+    }
+  }
+}
+
+impl std::error::Error for TftpError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      TftpError::Io(e) => Some(e),
+      _ => None
+    }
+  }
+}
+
+impl From<io::Error> for TftpError {
+  fn from(e: io::Error) -> Self {
+    TftpError::Io(e)
+  }
+}
+
+/// What a successful transfer did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferStats {
+  /// DATA blocks transferred, not counting retransmissions
+  pub blocos: u64,
+  /// bytes transferred in those blocks (in netascii mode, as sent on the wire)
+  pub bytes: u64,
+  /// how many times a packet was retransmitted
+  pub retransmissoes: u64,
+  /// from the first packet sent until the transfer finished
+  pub duracao: Duration
+}
+
+/// A callback that receives the file size reported by the server (RFC 2349 tsize).
+/// Returning false aborts the transfer before any DATA arrives
+#[derive(Clone)]
+struct TsizeHook(Arc<dyn Fn(u64) -> bool + Send + Sync>);
+
+impl fmt::Debug for TsizeHook {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "TsizeHook")
+  }
+}
+
+/// Follows the progress of a transfer. Every method has an empty default,
+/// so an observer implements only the events it cares about.
+/// Block numbers are the ones on the wire: they roll over after block 65535
+pub trait SessionObserver: Send + Sync {
+  /// a RRQ ("leitura" is true) or a WRQ for file "fname" was sent
+  fn request_sent(&self, _fname: &str, _leitura: bool) {}
+
+  /// options were negotiated with an OACK: "opcoes" are the ones in effect.
+  /// If the peer ignores the requested options, this is never called
+  fn negotiated(&self, _opcoes: &[(String, String)]) {}
+
+  /// DATA "block", with "len" bytes, was sent (also when retransmitted)
+  fn block_sent(&self, _block: u16, _len: usize) {}
+
+  /// the peer acknowledged every block up to "block"
+  fn block_acked(&self, _block: u16) {}
+
+  /// DATA "block", with "len" bytes, was received in order and stored
+  fn block_received(&self, _block: u16, _len: usize) {}
+
+  /// timed out waiting for an ACK: the window starting at "block" is sent again.
+  /// A receiver (or a sender still waiting for the transfer to start) instead sends
+  /// its last message again: the ACK of "block", or the request or OACK if "block" is 0.
+  /// "tentativa" counts the retransmissions of that window or message
+  fn retransmitted(&self, _block: u16, _tentativa: u16) {}
+
+  /// the transfer finished, successfully or not. Also called when it couldn't even
+  /// start (e.g. the server name didn't resolve, or a local file couldn't be opened),
+  /// and, for ClienteTFTP::get, only after the local file was replaced
+  fn finished(&self, _resultado: Result<&TransferStats, &TftpError>) {}
+}
+
+/// sets up the logs of the programs in this crate, which go to stderr. "nivel" is
+/// the filter used when RUST_LOG is not set; "json" logs each event as a JSON object
+pub fn inicia_logs(nivel: &str, json: bool) {
+  let filtro = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(nivel));
+  let logs = tracing_subscriber::fmt().with_env_filter(filtro).with_writer(io::stderr);
+  if json {
+    logs.json().init();
+  } else {
+    logs.init();
+  }
+}
+
+/// the span of a session, which identifies the transfer in its events.
+/// The TIDs are recorded as they become known
+fn session_span(op: &'static str, fname: &str) -> Span {
+  tracing::info_span!("sessao", op, fname, peer = Empty, tid = Empty)
+}
+
+#[derive(Clone)]
+struct Observer(Arc<dyn SessionObserver>);
+
+impl fmt::Debug for Observer {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Observer")
+  }
+}
+
+/// Where the contents to be transmitted come from
+struct Origem<'a>(Box<dyn AsyncRead + Unpin + Send + Sync + 'a>);
+
+/// Where received contents go to
+struct Destino<'a>(Box<dyn AsyncWrite + Unpin + Send + Sync + 'a>);
+
+impl fmt::Debug for Origem<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Origem")
+  }
+}
+
+impl fmt::Debug for Destino<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Destino")
+  }
 }
 
 #[derive(Debug)]
-struct Sessao {
+struct Sessao<'a> {
   sock: UdpSocket,
   server: SocketAddr,
   tid: bool,
-  buffer: BytesMut,
+  origem: Option<Origem<'a>>,
+  destino: Option<Destino<'a>>,
+  janela: VecDeque<Vec<u8>>,
+  pendente: BytesMut,
+  eof: bool,
+  ultimo_lido: bool,
   seqno: u16,
+  blocos: u64,
+  bytes: u64,
+  retransmissoes: u64,
+  inicio: Instant,
+  fim: Option<Instant>,
+  rollover: u16,
   timeout: u16,
+  rto: Duration,
+  rto_min: Duration,
+  rto_max: Duration,
+  rto_fixo: bool,
+  srtt: Option<Duration>,
+  rttvar: Duration,
+  enviado_em: Option<Instant>,
   retries: u16,
   max_retries: u16,
+  blksize: usize,
+  windowsize: u16,
+  enviados: u16,
+  recebidos: u16,
+  ultima: BytesMut,
+  dally: Option<Duration>,
+  tsize: Option<u64>,
+  tsize_hook: Option<TsizeHook>,
+  observer: Option<Observer>,
+  modo: msg::Modo,
+  encoder: netascii::Encoder,
+  decoder: netascii::Decoder,
+  opcoes: Vec<(String, String)>,
   estado: Estado,
-  status: Status
+  prazo: Instant,
+  manter_prazo: bool,
+  erro: Option<TftpError>
 }
 
 #[derive(Debug)]
@@ -48,47 +234,108 @@ enum Estado {
   InitTX,
   TX,
   FinishTX,
+  Dally,
   Finish
 }
 
 /// A Session is responsible for a file transfer (TX or RX).
-/// When RXing, the file contents will be stored in attribute "buffer"
-/// When TXing, file contents are first stored in "buffer", and then sent from there
-/// In the end, attribute "status" contains status of transmission (see enum Status)
-impl Sessao {
-  async fn new(server:&str, port:u16, timeout: u16, retries: u16) -> Option<Self> {
-    if let Some(ip) = Sessao::parse_ip(server) {
-      return Some(Sessao {
-        sock: UdpSocket::bind("0.0.0.0:0").await.expect("ao criar socket UDP"),
-        server: SocketAddr::new(IpAddr::V4(ip), port),
-        tid: false,
-        buffer: BytesMut::new(),
-        seqno: 1,
-        timeout: timeout,
-        retries: 0,
-        max_retries: retries,
-        estado: Estado::Idle,
-        status: Status::OK
-      });
-    }
-    None
+/// When RXing, the file contents are written to "destino" as blocks arrive in order
+/// When TXing, file contents are read from "origem" as needed: only the blocks of
+/// the current window are kept, in "janela"
+/// In the end, attribute "erro" tells why it failed, if it did (see "resultado")
+impl<'a> Sessao<'a> {
+  /// limits of the timeout option, in seconds (RFC 2349)
+  const MIN_TIMEOUT: u16 = 1;
+  const MAX_TIMEOUT: u16 = 255;
+
+  /// largest window a server session agrees to, as it keeps a whole window of
+  /// blocks in memory (RFC 7440 lets it answer less than requested)
+  const MAX_WINDOWSIZE: u16 = 64;
+
+  /// default bounds of the adaptive retransmission timeout
+  const RTO_MIN: Duration = Duration::from_millis(100);
+  const RTO_MAX: Duration = Duration::from_secs(60);
+
+  /// least time a receiver lingers after acknowledging the last block, unless
+  /// told otherwise (see "dally")
+  const DALLY: Duration = Duration::from_secs(1);
+
+  /// a timer deadline far enough to never expire
+  const SEMPRE: Duration = Duration::from_secs(100 * 365 * 24 * 3600);
+
+  async fn new(server:&str, port:u16, timeout: u16, retries: u16) -> Result<Self, TftpError> {
+    let addr = Sessao::resolve(server, port).await.ok_or_else(|| TftpError::Resolution(server.to_owned()))?;
+    // the socket must be of the same family as the server's address
+    let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let sock = UdpSocket::bind(local).await?;
+    let mut sessao = Sessao::with_socket(sock, addr, timeout, retries);
+    // the server's TID is only known when its first reply arrives
+    sessao.tid = false;
+    Ok(sessao)
   }
 
-  /// converts a string with an IP adress to a Ipv4Addr
-  /// is there a simpler way ???
-  fn parse_ip(ip: &str) -> Option<Ipv4Addr> {
-    let ip:Vec<_> = ip.split('.')
-                           .map(|c| c.parse::<u8>())
-                           .collect();
-    if ! ip.iter().any(|x| x.is_err()) {
-      if ip.len() == 4 {
-        let ip:Vec<_> = ip.into_iter()
-                          .map(|x| x.unwrap())
-                          .collect();
-        return Some(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]));
-      }
+  /// creates a session on an already bound socket, talking to a peer whose TID is known.
+  /// This is how the server side starts a session: "sock" is its new TID
+  fn with_socket(sock: UdpSocket, peer: SocketAddr, timeout: u16, retries: u16) -> Self {
+    // the session's span, if any, is completed with its TID
+    if let Ok(local) = sock.local_addr() {
+      Span::current().record("tid", local.port());
     }
-    None
+    Sessao {
+      sock,
+      server: peer,
+      tid: true,
+      origem: None,
+      destino: None,
+      janela: VecDeque::new(),
+      pendente: BytesMut::new(),
+      eof: false,
+      ultimo_lido: false,
+      seqno: 1,
+      blocos: 0,
+      bytes: 0,
+      retransmissoes: 0,
+      inicio: Instant::now(),
+      fim: None,
+      rollover: 0,
+      timeout,
+      rto: Duration::from_secs(timeout as u64),
+      rto_min: Sessao::RTO_MIN,
+      rto_max: Sessao::RTO_MAX,
+      rto_fixo: false,
+      srtt: None,
+      rttvar: Duration::ZERO,
+      enviado_em: None,
+      retries: 0,
+      max_retries: retries,
+      blksize: msg::DATA::SIZE,
+      windowsize: 1,
+      enviados: 0,
+      recebidos: 0,
+      ultima: BytesMut::new(),
+      dally: None,
+      tsize: None,
+      tsize_hook: None,
+      observer: None,
+      modo: msg::Modo::Octet,
+      encoder: netascii::Encoder::new(),
+      decoder: netascii::Decoder::new(),
+      opcoes: vec![],
+      estado: Estado::Idle,
+      prazo: Instant::now(),
+      manter_prazo: false,
+      erro: None
+    }
+  }
+
+  /// resolves a host name, or an IPv4 or IPv6 address, to a socket address.
+  /// Names are resolved by the system (so /etc/hosts is used), IPv6 addresses may
+  /// come between brackets, and link-local ones with a scope ("fe80::1%eth0")
+  async fn resolve(server: &str, port: u16) -> Option<SocketAddr> {
+    let host = server.strip_prefix('[')
+                     .and_then(|h| h.strip_suffix(']'))
+                     .unwrap_or(server);
+    tokio::net::lookup_host((host, port)).await.ok()?.next()
   }
 
   /// just checks if FSM is finished
@@ -114,39 +361,109 @@ impl Sessao {
           Estado::FinishTX => {
             self.handle_finish_tx(ev).await;
           }
+          Estado::Dally => {
+            self.handle_dally(ev).await;
+          }
           Estado::Finish => {
           }
           _ => {} // Idle 
         }       
     }
+    match &self.erro {
+      None => info!(blocos = self.blocos, bytes = self.bytes, "transfer finished"),
+      Some(erro) => info!(%erro, "transfer failed")
+    }
+  }
+
+  /// what the transfer did so far
+  fn stats(&self) -> TransferStats {
+    TransferStats {
+      blocos: self.blocos,
+      bytes: self.bytes,
+      retransmissoes: self.retransmissoes,
+      duracao: self.fim.unwrap_or_else(Instant::now) - self.inicio
+    }
+  }
+
+  /// the outcome of the finished transfer
+  fn resultado(self) -> Result<TransferStats, TftpError> {
+    match self.erro {
+      None => Ok(self.stats()),
+      Some(erro) => Err(erro)
+    }
+  }
+
+  /// notifies the observer, if there's one
+  fn notify<F>(&self, evento: F)
+  where F: FnOnce(&dyn SessionObserver) {
+    if let Some(observer) = &self.observer {
+      evento(observer.0.as_ref());
+    }
+  }
+
+  /// sets where the contents to be transmitted come from
+  fn set_origem<R>(&mut self, origem: R)
+  where R: AsyncRead + Unpin + Send + Sync + 'a {
+    self.origem = Some(Origem(Box::new(origem)));
+  }
+
+  /// sets where received contents go to
+  fn set_destino<W>(&mut self, destino: W)
+  where W: AsyncWrite + Unpin + Send + Sync + 'a {
+    self.destino = Some(Destino(Box::new(destino)));
+  }
+
+  /// writes a received block to "destino". In netascii mode, it's converted to local format.
+  /// After the last block, "destino" is flushed
+  async fn store(&mut self, body: &[u8], ultimo: bool) -> io::Result<()> {
+    let destino = match &mut self.destino {
+      Some(destino) => &mut destino.0,
+      None => return Ok(())
+    };
+    if self.modo == msg::Modo::Netascii {
+      destino.write_all(&self.decoder.decode(body)).await?;
+      if ultimo {
+        destino.write_all(&self.decoder.finish()).await?;
+      }
+    } else {
+      destino.write_all(body).await?;
+    }
+    if ultimo {
+      destino.flush().await?;
+    }
+    Ok(())
   }
 
-  /// starts transmission of a file: sends contents of "data" to a file named "fname"
-  async fn send(&mut self, fname: &str, data: &[u8]) {
+  /// starts transmission of a file: sends contents of "origem" to a file named "fname"
+  async fn send(&mut self, fname: &str) {
     if self.estado != Estado::Idle {
         panic!("sessão em uso");
     }
     
-    if let Some(req) = msg::Requisicao::new_wrq(fname, msg::Modo::Octet) {
-      let mesg = req.serialize();
-      println!("wrq: {:?}", mesg);
-      let n = self.sock.send_to(&mesg, self.server).await;
-      self.buffer.extend_from_slice(data);
+    if let Some(mut req) = msg::Requisicao::new_wrq(fname, self.modo) {
+      req.opcoes = self.opcoes.clone();
+      debug!(to = %self.server, modo = ?self.modo, opcoes = ?self.opcoes, "WRQ sent");
+      self.inicio = Instant::now();
+      self.send_and_keep(req.serialize()).await;
+      self.notify(|o| o.request_sent(fname, false));
+      self.start_rtt();
       self.estado = Estado::InitTX;
       self.run().await;
     }
   }
 
-  /// starts reception of file "fname".
-  /// In the end, its contents are contained in attribute "buffer"
+  /// starts reception of file "fname". Its contents are written to "destino"
   async fn receive(&mut self, fname: &str) -> Option<()>{
     if self.estado != Estado::Idle {
         panic!("sessão em uso");
     }
-    if let Some(req) = msg::Requisicao::new_rrq(fname, msg::Modo::Octet) {
-        let mesg = req.serialize();
-        println!("rrq: {:?}", mesg);
-        let n = self.sock.send_to(&mesg, self.server).await;
+    if let Some(mut req) = msg::Requisicao::new_rrq(fname, self.modo) {
+        req.opcoes = self.opcoes.clone();
+        debug!(to = %self.server, modo = ?self.modo, opcoes = ?self.opcoes, "RRQ sent");
+        self.inicio = Instant::now();
+        self.send_and_keep(req.serialize()).await;
+        self.notify(|o| o.request_sent(fname, true));
+        self.start_rtt();
         self.estado = Estado::RX;
         self.run().await;
         Some(())
@@ -156,63 +473,464 @@ impl Sessao {
     
   }
 
+  /// serves a RRQ (server side): sends contents of "origem" to the peer, starting with block 1.
+  /// "tsize" is its size, if known. If options were accepted, an OACK is sent first,
+  /// and block 1 waits for ACK 0
+  async fn serve_read(&mut self, tsize: Option<u64>, opcoes: &[(String, String)]) {
+    if self.estado != Estado::Idle {
+        panic!("sessão em uso");
+    }
+    self.seqno = 1;
+    self.tsize = tsize;
+    if let Some(oack) = msg::OACK::new(self.negotiate(opcoes)) {
+      self.send_and_keep(oack.serialize()).await;
+      self.notify(|o| o.negotiated(&oack.opcoes));
+      self.start_rtt();
+      self.estado = Estado::InitTX;
+    } else {
+      self.send_next().await;
+    }
+    self.run().await;
+  }
+
+  /// serves a WRQ (server side): answers with ACK 0 (or OACK) and receives the file,
+  /// writing it to "destino"
+  async fn serve_write(&mut self, opcoes: &[(String, String)]) {
+    if self.estado != Estado::Idle {
+        panic!("sessão em uso");
+    }
+    let resp = match msg::OACK::new(self.negotiate(opcoes)) {
+      Some(oack) => {
+        self.notify(|o| o.negotiated(&oack.opcoes));
+        oack.serialize()
+      }
+      None => msg::ACK { block: 0 }.serialize()
+    };
+    self.send_and_keep(resp).await;
+    self.start_rtt();
+    self.seqno = 1;
+    self.estado = Estado::RX;
+    self.run().await;
+  }
+
+  /// server side option negotiation: applies the options this session supports,
+  /// and returns them to be sent in an OACK. Unknown options are ignored (RFC 2347)
+  fn negotiate(&mut self, opcoes: &[(String, String)]) -> Vec<(String, String)> {
+    let mut aceitas = vec![];
+    for (nome, valor) in opcoes {
+      if let Some(valor) = self.accept_option(nome, valor) {
+        aceitas.push((nome.clone(), valor));
+      }
+    }
+    aceitas
+  }
+
+  /// client side: adds an option to be requested
+  fn request_option(&mut self, nome: &str, valor: &str) {
+    self.opcoes.push((nome.to_owned(), valor.to_owned()));
+  }
+
+  /// server side: decides the value of a requested option, and applies it.
+  /// Returns None if the option is not supported (or its value is not acceptable)
+  fn accept_option(&mut self, nome: &str, valor: &str) -> Option<String> {
+    match nome {
+      "blksize" => {
+        let blksize = valor.parse::<usize>().ok()?;
+        if blksize < msg::DATA::MIN_SIZE {
+          return None;
+        }
+        self.blksize = blksize.min(msg::DATA::MAX_SIZE);
+        Some(self.blksize.to_string())
+      }
+      "tsize" => {
+        // RRQ: the file size, if known, replaces the requested 0.
+        // WRQ: the size announced by the client is acknowledged
+        if self.origem.is_none() {
+          self.tsize = Some(valor.parse::<u64>().ok()?);
+        }
+        self.tsize.map(|tsize| tsize.to_string())
+      }
+      "rollover" => {
+        let rollover = valor.parse::<u16>().ok()?;
+        if rollover > 1 {
+          return None;
+        }
+        self.rollover = rollover;
+        Some(rollover.to_string())
+      }
+      "windowsize" => {
+        let windowsize = valor.parse::<u16>().ok()?;
+        if windowsize < 1 {
+          return None;
+        }
+        self.windowsize = windowsize.min(Sessao::MAX_WINDOWSIZE);
+        Some(self.windowsize.to_string())
+      }
+      "timeout" => {
+        let timeout = valor.parse::<u16>().ok()?;
+        if ! (Sessao::MIN_TIMEOUT..=Sessao::MAX_TIMEOUT).contains(&timeout) {
+          return None;
+        }
+        self.fix_timeout(timeout);
+        Some(timeout.to_string())
+      }
+      _ => None
+    }
+  }
+
+  /// client side: applies options acknowledged by the server in an OACK.
+  /// The server may only acknowledge options that were requested
+  fn apply_oack(&mut self, oack: &msg::OACK) -> bool {
+    oack.opcoes.iter().all(|(nome, valor)| self.apply_option(nome, valor))
+  }
+
+  /// client side: applies one option acknowledged by the server
+  fn apply_option(&mut self, nome: &str, valor: &str) -> bool {
+    let pedida = match self.opcoes.iter().find(|(pedida, _)| pedida == nome) {
+      Some((_, pedida)) => pedida,
+      None => return false
+    };
+    match nome {
+      "blksize" => {
+        // the server may only reduce the requested block size
+        match (valor.parse::<usize>(), pedida.parse::<usize>()) {
+          (Ok(blksize), Ok(max)) if (msg::DATA::MIN_SIZE..=max).contains(&blksize) => {
+            self.blksize = blksize;
+            true
+          }
+          _ => false
+        }
+      }
+      "tsize" => {
+        match valor.parse::<u64>() {
+          Ok(tsize) => {
+            self.tsize = Some(tsize);
+            true
+          }
+          _ => false
+        }
+      }
+      "rollover" => {
+        match valor.parse::<u16>() {
+          Ok(rollover) if rollover <= 1 => {
+            self.rollover = rollover;
+            true
+          }
+          _ => false
+        }
+      }
+      "windowsize" => {
+        // the server may only reduce the requested window size
+        match (valor.parse::<u16>(), pedida.parse::<u16>()) {
+          (Ok(windowsize), Ok(max)) if (1..=max).contains(&windowsize) => {
+            self.windowsize = windowsize;
+            true
+          }
+          _ => false
+        }
+      }
+      "timeout" => {
+        // the server must acknowledge exactly the requested timeout
+        if valor != pedida {
+          return false;
+        }
+        match valor.parse::<u16>() {
+          Ok(timeout) => {
+            self.fix_timeout(timeout);
+            true
+          }
+          _ => false
+        }
+      }
+      _ => true
+    }
+  }
+
+  /// client side: handles an OACK. If it can't be accepted, or the file size
+  /// is refused by the tsize hook, sends an ERR and finishes the FSM
+  async fn accept_oack(&mut self, oack: &msg::OACK) -> bool {
+    if ! self.apply_oack(oack) {
+      let erro = TftpError::Negotiation(format!("unacceptable {}", oack));
+      self.abort(ErrorCode::OptionRejected, "Option negotiation failed", erro).await;
+      return false;
+    }
+    if let (Estado::RX, Some(tsize), Some(hook)) = (&self.estado, self.tsize, &self.tsize_hook) {
+      if ! (hook.0)(tsize) {
+        let erro = TftpError::Negotiation(format!("file size {} refused", tsize));
+        self.abort(ErrorCode::DiskFull, "File too large", erro).await;
+        return false;
+      }
+    }
+    debug!(opcoes = ?oack.opcoes, "options negotiated");
+    self.notify(|o| o.negotiated(&oack.opcoes));
+    true
+  }
+
+  /// decodes a received message. A malformed message is an illegal TFTP operation:
+  /// it's answered with an ERR, and the FSM finishes
+  async fn decode(&mut self, buffer: Vec<u8>) -> Option<msg::Mensagem> {
+    match msg::from_bytes(buffer) {
+      Ok(mesg) => Some(mesg),
+      Err(e) => {
+        self.abort(ErrorCode::IllegalOperation, &e.to_string(), TftpError::Protocol(e.to_string())).await;
+        None
+      }
+    }
+  }
+
+  /// sends an ERR to the peer and finishes the FSM with error "erro"
+  async fn abort(&mut self, code: ErrorCode, text: &str, erro: TftpError) {
+    self.send_err(code, text).await;
+    self.estado = Estado::Finish;
+    self.erro = Some(erro);
+  }
+
+  /// the peer sent an ERR: the FSM finishes with it
+  fn peer_error(&mut self, err: msg::ERR) {
+    warn!(code = u16::from(err.err_code), text = %err.err_msg, "ERR received");
+    self.estado = Estado::Finish;
+    self.erro = Some(TftpError::Peer(err.err_code, err.err_msg));
+  }
+
+  /// a local read or write failed: sends the peer the matching ERR, and finishes
+  /// the FSM with that I/O error
+  async fn abort_io(&mut self, e: io::Error) {
+    let code = match e.kind() {
+      io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded | io::ErrorKind::FileTooLarge => ErrorCode::DiskFull,
+      io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => ErrorCode::AccessViolation,
+      _ => ErrorCode::NotDefined
+    };
+    let text = if code == ErrorCode::NotDefined { e.to_string() } else { code.to_string() };
+    warn!(error = %e, "local I/O error");
+    self.abort(code, &text, TftpError::Io(e)).await;
+  }
+
+  /// gives up on a peer that stopped answering. If its TID is known, it's told so
+  /// with an ERR, in case only the packets in the other direction are being lost
+  async fn give_up(&mut self) {
+    if self.tid {
+      self.send_err(ErrorCode::NotDefined, "Too many retransmissions").await;
+    }
+    self.estado = Estado::Finish;
+    self.erro = Some(TftpError::Timeout);
+  }
+
+  /// sends an error message to the peer
+  async fn send_err(&self, code: ErrorCode, text: &str) {
+    info!(code = u16::from(code), text, "ERR sent");
+    if let Some(err) = msg::ERR::new(code, text) {
+      self.send_msg(&err.serialize()).await;
+    }
+  }
+
+  /// sends a request, OACK or ACK, keeping it to be retransmitted if no reply arrives
+  async fn send_and_keep(&mut self, mesg: BytesMut) {
+    self.send_msg(&mesg).await;
+    self.ultima = mesg;
+  }
+
+  /// retransmits the last request, OACK or ACK, after backing off the RTO
+  /// if max retransmissions are exceeded, finishes the FSM
+  async fn resend(&mut self) {
+    if self.retries < self.max_retries {
+      self.retries += 1;
+      self.retransmissoes += 1;
+      self.backoff();
+      debug!(tentativa = self.retries, rto = ?self.rto, "retransmitting last message");
+      let block = if self.blocos == 0 { 0 } else { self.prev_block(self.seqno) };
+      self.notify(|o| o.retransmitted(block, self.retries));
+      self.send_msg(&self.ultima).await;
+    } else {
+      self.give_up().await;
+    }
+  }
+
+  /// sends an encoded message to the peer.
+  /// Send errors are not reported: they show up as timeouts
+  async fn send_msg(&self, mesg: &[u8]) {
+    let _ = self.sock.send_to(mesg, self.server).await;
+  }
+
+  /// the timeout option is in effect: the peer expects retransmissions after exactly
+  /// "timeout" seconds, so the retransmission timeout no longer adapts
+  fn fix_timeout(&mut self, timeout: u16) {
+    self.timeout = timeout;
+    self.rto = Duration::from_secs(timeout as u64);
+    self.rto_fixo = true;
+  }
+
+  /// how long to linger after acknowledging the last block: the period that was set,
+  /// or else twice as long as the sender waits before retransmitting it. That is the
+  /// negotiated timeout, if there's one, otherwise the RTO estimated on this side
+  fn dally(&self) -> Duration {
+    self.dally.unwrap_or_else(|| self.rto.saturating_mul(2).max(Sessao::DALLY))
+  }
+
+  /// how long to wait for the peer: the RTO, or the dally period after the last block
+  fn espera(&self) -> Duration {
+    match self.estado {
+      Estado::Dally => self.dally(),
+      _ => self.rto
+    }
+  }
+
+  /// starts measuring a round trip: a message that expects a reply was sent
+  fn start_rtt(&mut self) {
+    self.enviado_em = Some(Instant::now());
+  }
+
+  /// the reply to the measured message arrived: updates the RTT estimate and
+  /// the RTO (RFC 6298). Replies to retransmitted messages aren't measured, since
+  /// it's not known which copy was answered (Karn's algorithm)
+  fn sample_rtt(&mut self) {
+    let rtt = match self.enviado_em.take() {
+      Some(inicio) => inicio.elapsed(),
+      None => return
+    };
+    let srtt = match self.srtt {
+      None => {
+        self.rttvar = rtt / 2;
+        rtt
+      }
+      Some(srtt) => {
+        self.rttvar = (self.rttvar * 3 + srtt.abs_diff(rtt)) / 4;
+        (srtt * 7 + rtt) / 8
+      }
+    };
+    self.srtt = Some(srtt);
+    if ! self.rto_fixo {
+      let rto = srtt + (self.rttvar * 4).max(Duration::from_millis(1));
+      self.rto = rto.clamp(self.rto_min, self.rto_max);
+    }
+  }
+
+  /// sets the bounds of the adaptive RTO
+  fn set_rto_limits(&mut self, min: Duration, max: Duration) {
+    self.rto_min = min;
+    self.rto_max = max;
+    if ! self.rto_fixo {
+      self.rto = self.rto.clamp(min, max);
+    }
+  }
+
+  /// the timer expired: doubles the RTO, up to its ceiling
+  fn backoff(&mut self) {
+    self.enviado_em = None;
+    if ! self.rto_fixo {
+      self.rto = self.rto.saturating_mul(2).min(self.rto_max);
+    }
+  }
+
   /// waits for an event, and return it
   async fn get_event(&mut self) -> Evento {
-    let mut buf = [0; 1024];
-    let f_timeout = tokio::time::sleep(Duration::from_secs(self.timeout as u64));
+    // room for a DATA message with a full block
+    let mut buf = vec![0; self.blksize + 4];
+    // a packet that was ignored doesn't restart the timer: otherwise, a stream of
+    // duplicates could hold back retransmissions forever
+    if ! self.manter_prazo {
+      // a wait too long to be represented (e.g. a dally of Duration::MAX) never ends
+      let agora = Instant::now();
+      self.prazo = agora.checked_add(self.espera()).unwrap_or(agora + Sessao::SEMPRE);
+    }
+    self.manter_prazo = false;
+    let f_timeout = tokio::time::sleep_until(self.prazo);
 
     tokio::select! {
-      val = f_timeout, if self.timeout > 0 => {
-        println!("Timeout: val={:?}", val);
+      _ = f_timeout, if self.timeout > 0 => {
+        debug!(estado = ?self.estado, "timeout");
         return Evento::Timeout;
       }
       val = self.sock.recv_from(&mut buf) => {
         if let Ok((len,addr)) = val {
-          println!("Rx: val={:?}", (len,addr));
-          // TODO: conferir o ip do servidor
-          if ! self.tid {
+          trace!(len, from = %addr, "received");
+          let msg = buf[..len].to_vec();
+          if addr.ip() != self.server.ip() {
+            // not even the peer's host: probably spoofed, so it's not answered
+            debug!(from = %addr, "packet from unknown host ignored");
+          } else if ! self.tid && msg::from_bytes(msg.clone()).is_ok() {
+            // the first valid reply sets the peer's TID
             self.tid = true;
             self.server.set_port(addr.port());
-          }
-          if self.server == addr {
-            let msg = buf[..len].to_vec();
+            Span::current().record("peer", display(self.server));
+            return Evento::Msg(msg);
+          } else if self.tid && self.server == addr {
             return Evento::Msg(msg);
+          } else if self.tid {
+            // RFC 1350, section 4: the stray packet gets an ERR, without
+            // disturbing the transfer
+            debug!(from = %addr, "packet from unknown TID");
+            if let Some(err) = msg::ERR::new(ErrorCode::UnknownTid, "Unknown transfer ID") {
+              let _ = self.sock.send_to(&err.serialize(), addr).await;
+            }
           }
         }
       }
     }
+    self.manter_prazo = true;
     Evento::Nada
   }
   
   /// FSM handler for state RX
   async fn handle_rx(&mut self, ev: Evento) {
-    println!("rx");
-
     match ev {
         Evento::Timeout => {
-            self.estado = Estado::Finish;
-            self.status = Status::Timeout;
+            // the request (before the first block) or the last ACK was lost
+            self.resend().await;
         }
         Evento::Msg(buffer) => {
-            if let Some(mesg) = msg::from_bytes(buffer) {
+            if let Some(mesg) = self.decode(buffer).await {
                 match mesg {
                     msg::Mensagem::Data(data) => {
                         if data.block == self.seqno {
-                            self.seqno += 1;
-                            self.buffer.extend_from_slice(&data.body);
-                            if data.body.len() < msg::DATA::SIZE {
-                                self.estado = Estado::Finish;
+                            self.sample_rtt();
+                            self.retries = 0;
+                            self.seqno = self.next_block(self.seqno, 1);
+                            self.blocos += 1;
+                            self.bytes += data.body.len() as u64;
+                            self.recebidos += 1;
+                            let ultimo = data.body.len() < self.blksize;
+                            if let Err(e) = self.store(&data.body, ultimo).await {
+                                self.abort_io(e).await;
+                            } else {
+                                trace!(block = data.block, len = data.body.len(), "DATA received");
+                                self.notify(|o| o.block_received(data.block, data.body.len()));
+                                if ultimo {
+                                    // the dally period doesn't count
+                                    self.fim = Some(Instant::now());
+                                    self.estado = if self.dally().is_zero() { Estado::Finish } else { Estado::Dally };
+                                }
+                                // only the last block of a window is acknowledged (RFC 7440)
+                                if ultimo || self.recebidos == self.windowsize {
+                                    self.recebidos = 0;
+                                    self.send_ack(data.block).await;
+                                    self.start_rtt();
+                                }
                             }
+                        } else if (1..=self.recebidos).contains(&self.distance(data.block, self.seqno)) {
+                            // a copy of a block already received in this window: the
+                            // sender didn't go back, so the window goes on
+                            trace!(block = data.block, "duplicate DATA ignored");
+                        } else {
+                            // duplicate, or a block is missing: acknowledges the
+                            // last block received in order, so the sender goes back to it
+                            self.recebidos = 0;
+                            self.enviado_em = None;
+                            let block = if self.blocos == 0 { 0 } else { self.prev_block(self.seqno) };
+                            self.send_ack(block).await;
+                        }
+                    }
+                    msg::Mensagem::Oack(oack) if self.blocos == 0 => {
+                        // the OACK replaces DATA 1, and is confirmed with ACK 0
+                        self.sample_rtt();
+                        let aceito = self.accept_oack(&oack).await;
+                        if aceito {
+                            self.retries = 0;
+                            self.send_ack(0).await;
+                            self.start_rtt();
                         }
-                        if let Some(resp) = msg::ACK::new(data.block) {
-                            let mesg = resp.serialize();                                
-                            self.sock.send_to(&mesg, self.server).await;
-                        }                        
                     }
                     msg::Mensagem::Err(err) => {
-                        self.estado = Estado::Finish;
-                        self.status = Status::Error(err.err_code);
-
+                        self.peer_error(err);
                     }
                     _ => {
 
@@ -220,75 +938,197 @@ impl Sessao {
                 }
             }
         }
-        _ => {
-          println!("Alguma outra coisa ...");
-        }
+        _ => {}
     }    
-    println!("handle_rx: terminou");
   }
 
-  /// calculates current chunk size
-  fn get_chunk_size(&self) -> usize {
-    msg::DATA::SIZE.min(self.buffer.len())
+  /// calculates the block number "n" blocks after "block". After block 65535,
+  /// numbers roll over to 0, or to 1 if so negotiated ("rollover" option)
+  fn next_block(&self, block: u16, n: u16) -> u16 {
+    if self.rollover == 0 {
+      return block.wrapping_add(n);
+    }
+    ((block as u32 + u16::MAX as u32 - 1 + n as u32) % u16::MAX as u32 + 1) as u16
+  }
+
+  /// calculates the block number before "block"
+  fn prev_block(&self, block: u16) -> u16 {
+    if self.rollover == 1 && block == 1 {
+      return u16::MAX;
+    }
+    block.wrapping_sub(1)
+  }
+
+  /// calculates how many blocks "block" is after "base" (taking rollover into account)
+  fn distance(&self, base: u16, block: u16) -> u16 {
+    if self.rollover == 0 {
+      return block.wrapping_sub(base);
+    }
+    if block == 0 {
+      return u16::MAX;
+    }
+    ((block as u32 + u16::MAX as u32 - base as u32) % u16::MAX as u32) as u16
+  }
+
+  /// sends an ACK for block "block"
+  async fn send_ack(&mut self, block: u16) {
+    trace!(block, "ACK sent");
+    if let Some(resp) = msg::ACK::new(block) {
+      self.send_and_keep(resp.serialize()).await;
+    }
   }
 
-  /// sends a block of data ... the first available chunk in buffer
-  async fn send_data(&self) -> Option<bool> {
-    let body_len = self.get_chunk_size();
-    if let Some(data) = msg::DATA::new(self.seqno, &self.buffer[..body_len]) {
+  /// reads the next block from "origem": blksize bytes, unless it's the last block.
+  /// In netascii mode, contents are converted first
+  async fn read_block(&mut self) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; self.blksize];
+    while self.pendente.len() < self.blksize && ! self.eof {
+      let n = match &mut self.origem {
+        Some(origem) => origem.0.read(&mut buf).await?,
+        None => 0
+      };
+      self.eof = n == 0;
+      if self.modo == msg::Modo::Netascii {
+        let data = if self.eof { self.encoder.finish() } else { self.encoder.encode(&buf[..n]) };
+        self.pendente.extend_from_slice(&data);
+      } else {
+        self.pendente.extend_from_slice(&buf[..n]);
+      }
+    }
+    let len = self.blksize.min(self.pendente.len());
+    Ok(self.pendente.split_to(len).to_vec())
+  }
+
+  /// reads blocks from "origem" until the window is full, or the last block is read
+  async fn fill_window(&mut self) -> io::Result<()> {
+    while self.janela.len() < self.windowsize as usize && ! self.ultimo_lido {
+      let block = self.read_block().await?;
+      self.ultimo_lido = block.len() < self.blksize;
+      self.janela.push_back(block);
+    }
+    Ok(())
+  }
+
+  /// sends a block of data ... the i-th block in the window, which is block seqno+i.
+  /// Returns true if it was the last block
+  async fn send_data(&self, i: usize) -> Option<bool> {
+    let body = &self.janela[i];
+    let block = self.next_block(self.seqno, i as u16);
+    if let Some(data) = msg::DATA::new(block, body) {
       let mesg = data.serialize();                                
-      self.sock.send_to(&mesg, self.server).await;
-      return Some(body_len < msg::DATA::SIZE);
+      self.send_msg(&mesg).await;
+      trace!(block, len = body.len(), "DATA sent");
+      self.notify(|o| o.block_sent(block, body.len()));
+      return Some(body.len() < self.blksize);
     }
     None
   }    
+
+  /// sends the blocks in the window, starting at block seqno.
+  /// Returns true if the window contains the last block
+  async fn send_window(&mut self) -> Option<bool> {
+    self.enviados = 0;
+    let mut ultimo = false;
+    while (self.enviados as usize) < self.janela.len() {
+      ultimo = self.send_data(self.enviados as usize).await?;
+      self.enviados += 1;
+    }
+    Some(ultimo)
+  }
   
-  /// retransmits last block of data
+  /// retransmits the current window, after backing off the RTO
   /// if max retransmissions are exceeded, finishes the FSM
   async fn retransmit(&mut self) {
     if self.retries < self.max_retries {
       self.retries+=1;
-      self.send_data().await;
+      self.retransmissoes += 1;
+      self.backoff();
+      debug!(block = self.seqno, tentativa = self.retries, rto = ?self.rto, "retransmitting");
+      self.notify(|o| o.retransmitted(self.seqno, self.retries));
+      self.send_window().await;
     } else {
-      self.estado = Estado::Finish;
-      self.status = Status::MaxRetriesExceeded;
+      self.give_up().await;
     }
   }
 
-  /// sends next block of data, and updates state accordingly
+  /// sends next window of data, and updates state accordingly
   async fn send_next(&mut self) {
-    match self.send_data().await {
+    if let Err(e) = self.fill_window().await {
+      self.abort_io(e).await;
+      return;
+    }
+    self.start_rtt();
+    match self.send_window().await {
       Some(true) => self.estado = Estado::FinishTX,
       Some(false) => self.estado = Estado::TX,
       None => self.estado = Estado::Finish
     }
   }
 
+  /// handles an ACK in states TX and FinishTX. Only ACKs for blocks of the current
+  /// window count. If it's not the window's last block, the receiver missed the
+  /// next one, and transmission goes back to it (RFC 7440).
+  /// Duplicate ACKs are ignored: DATA is retransmitted only when the timer expires,
+  /// so a delayed ACK doesn't double the traffic (Sorcerer's Apprentice, RFC 1123 4.2.3.1)
+  async fn handle_ack(&mut self, block: u16) {
+    let n = self.distance(self.seqno, block);
+    if n >= self.enviados {
+      self.manter_prazo = true;
+      return;
+    }
+    trace!(block, "ACK received");
+    self.notify(|o| o.block_acked(block));
+    self.sample_rtt();
+    let volta = n + 1 < self.enviados;
+    self.blocos += n as u64 + 1;
+    self.bytes += self.janela.drain(..=n as usize).map(|body| body.len() as u64).sum::<u64>();
+    if self.estado == Estado::FinishTX && ! volta {
+      self.estado = Estado::Finish;
+      return;
+    }
+    self.seqno = self.next_block(block, 1);
+    self.retries = 0;
+    self.send_next().await;
+    if volta {
+      // blocks sent again aren't measured
+      self.enviado_em = None;
+    }
+  }
+
   /// FSM handler for state InitTX
   async fn handle_init_tx(&mut self, ev: Evento) {
-    println!("init-tx");
     match ev {
       Evento::Timeout => {
-        // aborts
-        self.estado = Estado::Finish;
-        self.status = Status::Timeout;
+        // the WRQ or the OACK was lost
+        self.resend().await;
       }
       Evento::Msg(buffer) => {
-          if let Some(mesg) = msg::from_bytes(buffer) {
+          if let Some(mesg) = self.decode(buffer).await {
               match mesg {
                   msg::Mensagem::Ack(ack) => {
                       if ack.block == 0 {
+                          self.sample_rtt();
                           self.seqno = 1;
                           self.retries = 0;
                           self.send_next().await;
                                                     
                       } else {
-                        self.estado = Estado::Finish;
+                        let text = format!("ACK {} before the transfer started", ack.block);
+                        self.abort(ErrorCode::IllegalOperation, &text, TftpError::Protocol(text.clone())).await;
                       }                   
                   }
+                  msg::Mensagem::Oack(oack) => {
+                      // the OACK replaces ACK 0
+                      self.sample_rtt();
+                      let aceito = self.accept_oack(&oack).await;
+                      if aceito {
+                          self.seqno = 1;
+                          self.retries = 0;
+                          self.send_next().await;
+                      }
+                  }
                   msg::Mensagem::Err(err) => {
-                      self.estado = Estado::Finish;
-                      self.status = Status::Error(err.err_code)
+                      self.peer_error(err);
                   }
                   _ => {
 
@@ -296,30 +1136,45 @@ impl Sessao {
               }
           }
       }
-      _ => {
-        println!("Alguma outra coisa ...");
-      }
+      _ => {}
   }    
 }
 
+  /// FSM handler for state Dally: the last block was received and acknowledged, but
+  /// that ACK may be lost. Until the sender goes quiet, any DATA it retransmits is
+  /// acknowledged again (RFC 1350, section 6)
+  async fn handle_dally(&mut self, ev: Evento) {
+    match ev {
+      Evento::Timeout => self.estado = Estado::Finish,
+      Evento::Msg(buffer) => {
+        match msg::from_bytes(buffer) {
+          Ok(msg::Mensagem::Data(data)) => {
+            debug!(block = data.block, "DATA retransmitted after the last block, ACK resent");
+            self.send_msg(&self.ultima).await;
+          }
+          // the transfer is complete: anything else is ignored, and doesn't
+          // extend the dally period
+          _ => self.manter_prazo = true
+        }
+      }
+      _ => {}
+    }
+  }
+
   /// FSM handler for state FinishTx
   async fn handle_finish_tx(&mut self, ev: Evento) {
-    println!("finish-tx");
     match ev {
       Evento::Timeout => {
         self.retransmit().await;
       }
       Evento::Msg(buffer) => {
-          if let Some(mesg) = msg::from_bytes(buffer) {
+          if let Some(mesg) = self.decode(buffer).await {
               match mesg {
                   msg::Mensagem::Ack(ack) => {
-                      if ack.block == self.seqno {
-                        self.estado = Estado::Finish;
-                      }                    
+                      self.handle_ack(ack.block).await;
                   }
                   msg::Mensagem::Err(err) => {
-                      self.estado = Estado::Finish;
-                      self.status = Status::Error(err.err_code)
+                      self.peer_error(err);
                   }
                   _ => {
 
@@ -327,34 +1182,25 @@ impl Sessao {
               }
           }
       }
-      _ => {
-        println!("Alguma outra coisa ...");
-      }
+      _ => {}
     }
   }
 
   /// FSM handler for state TX
   async fn handle_tx(&mut self, ev: Evento) {
-    println!("tx");
     match ev {
       Evento::Timeout => {
         // retransmits a block
         self.retransmit().await;
       }
       Evento::Msg(buffer) => {
-          if let Some(mesg) = msg::from_bytes(buffer) {
+          if let Some(mesg) = self.decode(buffer).await {
               match mesg {
                   msg::Mensagem::Ack(ack) => {
-                      if ack.block == self.seqno {
-                          self.seqno += 1;
-                          self.retries = 0;
-                          self.buffer.split_to(self.get_chunk_size());
-                          self.send_next().await;
-                      }                    
+                      self.handle_ack(ack.block).await;
                   }
                   msg::Mensagem::Err(err) => {
-                      self.estado = Estado::Finish;
-                      self.status = Status::Error(err.err_code)
+                      self.peer_error(err);
                   }
                   _ => {
 
@@ -362,9 +1208,7 @@ impl Sessao {
               }
           }
       }
-      _ => {
-        println!("Alguma outra coisa ...");
-      }
+      _ => {}
   }    
 }
 }
@@ -372,69 +1216,440 @@ impl Sessao {
 #[derive(Debug)]
 pub struct ClienteTFTP {
     server: String,
-    port: u16
+    port: u16,
+    blksize: Option<usize>,
+    timeout: Option<u16>,
+    windowsize: Option<u16>,
+    rollover: Option<u16>,
+    retries: u16,
+    rto_limits: Option<(Duration, Duration)>,
+    dally: Option<Duration>,
+    modo: msg::Modo,
+    tsize_hook: Option<TsizeHook>,
+    observer: Option<Observer>
 }
 
 impl ClienteTFTP {
     pub fn new(server: &str, port: u16) -> Self {
         ClienteTFTP {
             server: server.to_owned(),
-            port: port
+            port,
+            blksize: None,
+            timeout: None,
+            windowsize: None,
+            rollover: None,
+            retries: 3,
+            rto_limits: None,
+            dally: None,
+            modo: msg::Modo::Octet,
+            tsize_hook: None,
+            observer: None
         }
     }
 
-    async fn do_send(&self, fname: &str) -> Option<Sessao> {
-        if let Ok(data) = fs::read(fname) {
-            
-            if let Some(mut sessao) = Sessao::new(&self.server, self.port, 1, 3).await {
-              sessao.send(fname, &data).await;                                
+    /// block size to be requested (RFC 2348). Values outside 8..=65464 are refused.
+    /// Without it, transfers use the default 512 bytes block
+    pub fn set_blksize(&mut self, blksize: usize) -> bool {
+        if (msg::DATA::MIN_SIZE..=msg::DATA::MAX_SIZE).contains(&blksize) {
+            self.blksize = Some(blksize);
+            return true;
+        }
+        false
+    }
 
-              return Some(sessao);
-            }
+    /// retransmission timeout, in seconds, to be requested (RFC 2349).
+    /// Values outside 1..=255 are refused. Without it, the timeout is 1 s,
+    /// and it is not negotiated
+    pub fn set_timeout(&mut self, timeout: u16) -> bool {
+        if (Sessao::MIN_TIMEOUT..=Sessao::MAX_TIMEOUT).contains(&timeout) {
+            self.timeout = Some(timeout);
+            return true;
         }
-        None
-      }
-      
-      async fn do_receive(&self, fname: &str) -> Option<Sessao> {
-        if let Some(mut sessao) = Sessao::new(&self.server, self.port, 1, 3).await {
-          sessao.receive(fname).await;
+        false
+    }
 
-          return Some(sessao);
+    /// number of blocks sent before waiting for an ACK, to be requested (RFC 7440).
+    /// Zero is refused. Without it, each block waits for its ACK
+    pub fn set_windowsize(&mut self, windowsize: u16) -> bool {
+        if windowsize > 0 {
+            self.windowsize = Some(windowsize);
+            return true;
         }
-        None
-      }
+        false
+    }
 
-    pub fn envia(&self, fname: &str) -> Status {
-        let mut rt = tokio::runtime::Runtime::new().expect("");
-        // let mut rt = tokio::runtime::Builder::new_multi_thread()
-        // .worker_threads(1)
-        // .enable_all()
-        // .build()
-        // .expect("Não conseguiu iniciar runtime !");
-    
-        if let Some(sessao) = rt.block_on(self.do_send(fname)) {
-          sessao.status
-        } else {
-          Status::Unknown
+    /// block number that follows block 65535, to be requested with the "rollover"
+    /// option: 0 or 1. Without it, numbers roll over to 0, as most servers do
+    pub fn set_rollover(&mut self, rollover: u16) -> bool {
+        if rollover <= 1 {
+            self.rollover = Some(rollover);
+            return true;
         }
+        false
+    }
 
+    /// how many times a packet (a window of DATA, a request or an ACK) is retransmitted
+    /// before giving up. Default is 3
+    pub fn set_retries(&mut self, retries: u16) {
+        self.retries = retries;
     }
 
-    pub fn recebe(&self, fname: &str, local: &str) -> Status {
-        let mut rt = tokio::runtime::Runtime::new().expect("");
-        // let mut rt = tokio::runtime::Builder::new_multi_thread()
-        // .worker_threads(1)
-        // .enable_all()
-        // .build()
-        // .expect("Não conseguiu iniciar runtime !");
-    
-        let r = rt.block_on(self.do_receive(fname));
-        if let Some(sessao) = r {
-          fs::write("local", sessao.buffer);
-          sessao.status
+    /// bounds of the retransmission timeout. It starts at the timeout (1 s by default),
+    /// and then follows the measured round trip time, doubling at each retransmission.
+    /// Refused if "min" is zero or greater than "max". Without it, it's kept between
+    /// 100 ms and 60 s. If the timeout option is negotiated, the RTO is fixed at that timeout
+    pub fn set_rto_limits(&mut self, min: Duration, max: Duration) -> bool {
+        if min.is_zero() || min > max {
+            return false;
+        }
+        self.rto_limits = Some((min, max));
+        true
+    }
+
+    /// how long a receiving session lingers after acknowledging the last block, so
+    /// that ACK is sent again if the server retransmits that block. It should exceed
+    /// the server's retransmission timeout. By default, it's twice the negotiated
+    /// timeout, or twice the current RTO, and at least 1 s; zero disables it
+    pub fn set_dally(&mut self, dally: Duration) {
+        self.dally = Some(dally);
+    }
+
+    /// transfer mode: octet (the default) or netascii, where line endings are
+    /// converted to and from CR LF. Mail mode is refused
+    pub fn set_modo(&mut self, modo: msg::Modo) -> bool {
+        if modo == msg::Modo::Mail {
+            return false;
+        }
+        self.modo = modo;
+        true
+    }
+
+    /// registers a callback that receives the size of files being received, when
+    /// the server reports it (RFC 2349). It can be used to pre-allocate space or
+    /// to show progress. If it returns false, the transfer is aborted before any DATA
+    pub fn on_tsize<F>(&mut self, hook: F)
+    where F: Fn(u64) -> bool + Send + Sync + 'static {
+        self.tsize_hook = Some(TsizeHook(Arc::new(hook)));
+    }
+
+    /// registers an observer, which follows the progress of every transfer
+    /// (see SessionObserver)
+    pub fn set_observer(&mut self, observer: Arc<dyn SessionObserver>) {
+        self.observer = Some(Observer(observer));
+    }
+
+    /// creates a session, with the options to be requested
+    async fn new_session<'a>(&self) -> Result<Sessao<'a>, TftpError> {
+        let mut sessao = Sessao::new(&self.server, self.port, self.timeout.unwrap_or(1), self.retries).await?;
+        if let Some(blksize) = self.blksize {
+            sessao.request_option("blksize", &blksize.to_string());
+        }
+        if let Some(timeout) = self.timeout {
+            sessao.request_option("timeout", &timeout.to_string());
+        }
+        if let Some(windowsize) = self.windowsize {
+            sessao.request_option("windowsize", &windowsize.to_string());
+        }
+        if let Some(rollover) = self.rollover {
+            sessao.request_option("rollover", &rollover.to_string());
+        }
+        if let Some((min, max)) = self.rto_limits {
+            sessao.set_rto_limits(min, max);
+        }
+        sessao.dally = self.dally;
+        sessao.tsize_hook = self.tsize_hook.clone();
+        sessao.observer = self.observer.clone();
+        sessao.modo = self.modo;
+        Ok(sessao)
+    }
+
+    async fn do_send<'a, R>(&self, origem: R, remote: &str, tsize: Option<u64>) -> Result<TransferStats, TftpError>
+    where R: AsyncRead + Unpin + Send + Sync + 'a {
+        async move {
+          let mut sessao = self.new_session().await?;
+          // in netascii mode, the size on the wire is only known after the conversion
+          if let (Some(tsize), msg::Modo::Octet) = (tsize, self.modo) {
+            sessao.request_option("tsize", &tsize.to_string());
+          }
+          sessao.set_origem(origem);
+          sessao.send(remote).await;
+          sessao.resultado()
+        }.instrument(session_span("wrq", remote)).await
+    }
+
+    async fn do_receive<'a, W>(&self, remote: &str, destino: W) -> Result<TransferStats, TftpError>
+    where W: AsyncWrite + Unpin + Send + Sync + 'a {
+        async move {
+          let mut sessao = self.new_session().await?;
+          sessao.request_option("tsize", "0");
+          sessao.set_destino(destino);
+          sessao.receive(remote).await;
+          sessao.resultado()
+        }.instrument(session_span("rrq", remote)).await
+    }
+
+    /// sends the contents of "origem" to file "remote" in the server.
+    /// "tsize" is the size of the contents, if known beforehand.
+    /// Runs on the caller's runtime
+    pub async fn put_from<R>(&self, origem: R, remote: &str, tsize: Option<u64>) -> Result<TransferStats, TftpError>
+    where R: AsyncRead + Unpin + Send + Sync {
+        self.finished(self.do_send(origem, remote, tsize).await)
+    }
+
+    /// receives file "remote" from the server, writing its contents to "destino".
+    /// Runs on the caller's runtime
+    pub async fn get_into<W>(&self, remote: &str, destino: W) -> Result<TransferStats, TftpError>
+    where W: AsyncWrite + Unpin + Send + Sync {
+        self.finished(self.do_receive(remote, destino).await)
+    }
+
+    /// sends local file "local" to file "remote" in the server, running on the caller's runtime
+    pub async fn put(&self, local: &str, remote: &str) -> Result<TransferStats, TftpError> {
+        self.finished(self.do_put(local, remote).await)
+    }
+
+    async fn do_put(&self, local: &str, remote: &str) -> Result<TransferStats, TftpError> {
+        let file = File::open(local).await?;
+        let tsize = file.metadata().await.ok().map(|meta| meta.len());
+        self.do_send(file, remote, tsize).await
+    }
+
+    /// receives file "remote" from the server, writing it to local file "local".
+    /// Contents go to a temporary file in the same directory, which replaces "local"
+    /// only if the transfer succeeds: otherwise, "local" is left as it was.
+    /// Runs on the caller's runtime
+    pub async fn get(&self, remote: &str, local: &str) -> Result<TransferStats, TftpError> {
+        self.finished(self.do_get(remote, local).await)
+    }
+
+    async fn do_get(&self, remote: &str, local: &str) -> Result<TransferStats, TftpError> {
+        let temp = ClienteTFTP::temp_path(local);
+        let file = OpenOptions::new().write(true).create_new(true).open(&temp).await?;
+        let resultado = self.do_receive(remote, file).await;
+        if resultado.is_ok() {
+          if let Err(e) = tokio::fs::rename(&temp, local).await {
+            let _ = tokio::fs::remove_file(&temp).await;
+            return Err(e.into());
+          }
         } else {
-          Status::Unknown
+          let _ = tokio::fs::remove_file(&temp).await;
         }
+        resultado
+    }
+
+    /// tells the observer, if there's one, how a transfer ended, and returns its result
+    fn finished(&self, resultado: Result<TransferStats, TftpError>) -> Result<TransferStats, TftpError> {
+        if let Some(observer) = &self.observer {
+            observer.0.finished(resultado.as_ref());
+        }
+        resultado
+    }
+
+    /// a temporary file for a download to "local", next to it so that it can be
+    /// renamed over it
+    fn temp_path(local: &str) -> PathBuf {
+        static SEQ: AtomicU32 = AtomicU32::new(0);
+        let path = Path::new(local);
+        let nome = path.file_name().map(|nome| nome.to_string_lossy()).unwrap_or_default();
+        let temp = format!(".{}.{}-{}.part", nome, std::process::id(), SEQ.fetch_add(1, Ordering::Relaxed));
+        path.with_file_name(temp)
+    }
+
+    /// blocking version of "put": it must not be called from within a Tokio runtime
+    pub fn envia(&self, fname: &str) -> Result<TransferStats, TftpError> {
+        let rt = match tokio::runtime::Runtime::new() {
+            Ok(rt) => rt,
+            Err(e) => return self.finished(Err(e.into()))
+        };
+        rt.block_on(self.put(fname, fname))
+    }
+
+    /// blocking version of "get": it must not be called from within a Tokio runtime
+    pub fn recebe(&self, fname: &str, local: &str) -> Result<TransferStats, TftpError> {
+        let rt = match tokio::runtime::Runtime::new() {
+            Ok(rt) => rt,
+            Err(e) => return self.finished(Err(e.into()))
+        };
+        rt.block_on(self.get(fname, local))
+    }
+}
+
+
+#[derive(Debug)]
+pub struct ServidorTFTP {
+    dir: PathBuf,
+    port: u16,
+    sessoes: Arc<Mutex<HashSet<SocketAddr>>>
+}
+
+/// A TFTP server: files are served from (and written into) directory "dir".
+/// Each request gets its own session, running on a new socket (that is, a new TID).
+/// A client has at most one session at a time: the peers with a session running are
+/// kept in "sessoes"
+impl ServidorTFTP {
+    /// well known TFTP port
+    pub const PORT: u16 = 69;
+
+    pub fn new(dir: &str, port: u16) -> Self {
+        ServidorTFTP {
+            dir: PathBuf::from(dir),
+            port,
+            sessoes: Arc::new(Mutex::new(HashSet::new()))
+        }
+    }
+
+    /// maps a requested file name to a path inside "dir".
+    /// Absolute names and names with ".." are refused
+    fn get_path(&self, fname: &str) -> Option<PathBuf> {
+        let path = Path::new(fname);
+        if path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Some(self.dir.join(path));
+        }
+        None
+    }
+
+    /// waits for requests on the well known port, and starts a session for each of them.
+    /// Requests are taken over IPv6 and IPv4: by a dual-stack socket if the system
+    /// provides one, otherwise by a socket for each family
+    async fn do_run(&self) -> Result<(), TftpError> {
+        let v6 = UdpSocket::bind(("::", self.port)).await;
+        // if the IPv6 socket is dual-stack, it has taken the IPv4 port too
+        let v4 = UdpSocket::bind(("0.0.0.0", self.port)).await;
+        let (v6, v4) = match (v6, v4) {
+            (Err(_), Err(e)) => return Err(e.into()),
+            (v6, v4) => (v6.ok(), v4.ok())
+        };
+        let mut buf6 = [0; 1024];
+        let mut buf4 = [0; 1024];
+        loop {
+            tokio::select! {
+                Ok((len, addr)) = ServidorTFTP::recv(&v6, &mut buf6) => {
+                    self.handle_request(v6.as_ref().unwrap(), &buf6[..len], addr).await;
+                }
+                Ok((len, addr)) = ServidorTFTP::recv(&v4, &mut buf4) => {
+                    self.handle_request(v4.as_ref().unwrap(), &buf4[..len], addr).await;
+                }
+                // receive errors are ignored, as with a single socket
+                else => {}
+            }
+        }
+    }
+
+    /// receives from "sock", if there is one: otherwise, never returns
+    async fn recv(sock: &Option<UdpSocket>, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match sock {
+            Some(sock) => sock.recv_from(buf).await,
+            None => std::future::pending().await
+        }
+    }
+
+    /// starts a session for a request that arrived at the well known port "sock"
+    async fn handle_request(&self, sock: &UdpSocket, buf: &[u8], addr: SocketAddr) {
+        match msg::from_bytes(buf.to_vec()) {
+            Ok(msg::Mensagem::Rrq(req)) => {
+                info!(peer = %addr, "{}", req);
+                let span = session_span("rrq", &req.fname);
+                let sessao = ServidorTFTP::serve_rrq(self.get_path(&req.fname), addr, req.modo, req.opcoes);
+                self.start_session(addr, sessao.instrument(span));
+            }
+            Ok(msg::Mensagem::Wrq(req)) => {
+                info!(peer = %addr, "{}", req);
+                let span = session_span("wrq", &req.fname);
+                let sessao = ServidorTFTP::serve_wrq(self.get_path(&req.fname), addr, req.modo, req.opcoes);
+                self.start_session(addr, sessao.instrument(span));
+            }
+            Err(e) => {
+                // a malformed request is answered with an ERR, directly from the well known port
+                warn!(peer = %addr, error = %e, "malformed request");
+                if let Some(err) = msg::ERR::new(ErrorCode::IllegalOperation, &e.to_string()) {
+                    let _ = sock.send_to(&err.serialize(), addr).await;
+                }
+            }
+            _ => {} // only requests are expected at the well known port
+        }
+    }
+
+    /// runs "sessao" in a new task, unless "peer" already has a session running: then
+    /// the request is a retransmission (e.g. the reply to it was lost), and is ignored.
+    /// Otherwise a second session would answer it from another TID
+    fn start_session<F>(&self, peer: SocketAddr, sessao: F)
+    where F: Future<Output = ()> + Send + 'static {
+        let peer = SocketAddr::new(peer.ip().to_canonical(), peer.port());
+        if ! self.sessoes.lock().unwrap().insert(peer) {
+            debug!(%peer, "duplicate request ignored");
+            return;
+        }
+        let sessoes = self.sessoes.clone();
+        tokio::spawn(async move {
+            sessao.await;
+            sessoes.lock().unwrap().remove(&peer);
+        });
+    }
+
+    /// creates the session for a request, on a new ephemeral socket
+    async fn new_session(peer: SocketAddr, modo: msg::Modo) -> Option<Sessao<'static>> {
+        // an IPv4 client that reached the dual-stack socket is answered over IPv4,
+        // and the socket must be of the same family as the client's address
+        let peer = SocketAddr::new(peer.ip().to_canonical(), peer.port());
+        let local = if peer.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        if let Ok(sock) = UdpSocket::bind(local).await {
+            Span::current().record("peer", display(peer));
+            let mut sessao = Sessao::with_socket(sock, peer, 1, 3);
+            sessao.modo = modo;
+            return Some(sessao);
+        }
+        None
+    }
+
+    /// handles a RRQ: sends the requested file to "peer"
+    async fn serve_rrq(path: Option<PathBuf>, peer: SocketAddr, modo: msg::Modo, opcoes: Vec<(String, String)>) {
+        if let Some(mut sessao) = ServidorTFTP::new_session(peer, modo).await {
+            match path {
+                _ if modo == msg::Modo::Mail => sessao.send_err(ErrorCode::IllegalOperation, "Mail mode not supported").await,
+                None => sessao.send_err(ErrorCode::AccessViolation, "Access violation").await,
+                Some(path) => match File::open(&path).await {
+                    Ok(file) => {
+                        // in netascii mode, the size on the wire is only known after the conversion
+                        let tsize = match file.metadata().await {
+                            Ok(meta) if modo == msg::Modo::Octet => Some(meta.len()),
+                            _ => None
+                        };
+                        sessao.set_origem(file);
+                        sessao.serve_read(tsize, &opcoes).await;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::PermissionDenied => sessao.send_err(ErrorCode::AccessViolation, "Access violation").await,
+                    Err(_) => sessao.send_err(ErrorCode::FileNotFound, "File not found").await
+                }
+            }
+        }
+    }
+
+    /// handles a WRQ: receives a file from "peer", writing it as blocks arrive.
+    /// If the transfer fails, the partial file is removed
+    async fn serve_wrq(path: Option<PathBuf>, peer: SocketAddr, modo: msg::Modo, opcoes: Vec<(String, String)>) {
+        if let Some(mut sessao) = ServidorTFTP::new_session(peer, modo).await {
+            match path {
+                _ if modo == msg::Modo::Mail => sessao.send_err(ErrorCode::IllegalOperation, "Mail mode not supported").await,
+                None => sessao.send_err(ErrorCode::AccessViolation, "Access violation").await,
+                Some(path) => match OpenOptions::new().write(true).create_new(true).open(&path).await {
+                    Ok(file) => {
+                        sessao.set_destino(file);
+                        sessao.serve_write(&opcoes).await;
+                        if sessao.erro.is_some() {
+                            let _ = tokio::fs::remove_file(&path).await;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => sessao.send_err(ErrorCode::FileExists, "File already exists").await,
+                    Err(_) => sessao.send_err(ErrorCode::AccessViolation, "Access violation").await
+                }
+            }
+        }
+    }
+
+    /// runs the server. It only returns if the well known port can't be bound
+    pub fn executa(&self) -> Result<(), TftpError> {
+        let rt = tokio::runtime::Runtime::new()?;
+        rt.block_on(self.do_run())
     }
 }
 
@@ -452,3 +1667,58 @@ impl ClienteTFTP {
 
 //     Ok(())
 // }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// a session that only does block arithmetic, with the given "rollover"
+  async fn sessao(rollover: u16) -> Sessao<'static> {
+    let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer = sock.local_addr().unwrap();
+    let mut sessao = Sessao::with_socket(sock, peer, 1, 3);
+    sessao.rollover = rollover;
+    sessao
+  }
+
+  #[tokio::test]
+  async fn blocks_roll_over_to_0() {
+    let s = sessao(0).await;
+    assert_eq!(s.next_block(65534, 1), 65535);
+    assert_eq!(s.next_block(65535, 1), 0);
+    assert_eq!(s.next_block(0, 1), 1);
+    assert_eq!(s.next_block(65534, 3), 1);
+
+    assert_eq!(s.prev_block(65535), 65534);
+    assert_eq!(s.prev_block(0), 65535);
+    assert_eq!(s.prev_block(1), 0);
+
+    assert_eq!(s.distance(65534, 65535), 1);
+    assert_eq!(s.distance(65534, 0), 2);
+    assert_eq!(s.distance(65535, 1), 2);
+    assert_eq!(s.distance(0, 0), 0);
+    // a block before "base" is far away
+    assert_eq!(s.distance(1, 0), u16::MAX);
+  }
+
+  #[tokio::test]
+  async fn blocks_roll_over_to_1() {
+    let s = sessao(1).await;
+    assert_eq!(s.next_block(65534, 1), 65535);
+    assert_eq!(s.next_block(65535, 1), 1);
+    assert_eq!(s.next_block(1, 1), 2);
+    assert_eq!(s.next_block(65534, 3), 2);
+
+    assert_eq!(s.prev_block(65535), 65534);
+    assert_eq!(s.prev_block(1), 65535);
+    assert_eq!(s.prev_block(2), 1);
+
+    assert_eq!(s.distance(65534, 65535), 1);
+    assert_eq!(s.distance(65535, 1), 1);
+    assert_eq!(s.distance(65534, 2), 3);
+    assert_eq!(s.distance(1, 1), 0);
+    assert_eq!(s.distance(2, 1), 65534);
+    // block 0 is never used
+    assert_eq!(s.distance(65535, 0), u16::MAX);
+  }
+}
@@ -1,27 +1,261 @@
-use clap::Parser;
-use tftp::{ClienteTFTP,Status};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+use clap::{Parser, Subcommand, ValueEnum};
+use tftp::{ClienteTFTP, SessionObserver, TftpError, TransferStats};
+use tftp::msg;
 
-/// Um pequeno cliente TFTP experimental
+mod shell;
+
+/// Um pequeno cliente TFTP experimental. Sem um comando, abre um prompt interativo.
+///
+/// Códigos de saída: 0 = sucesso, 1 = erro local, 2 = timeout, 3 = erro de protocolo,
+/// 4 = erro informado pelo servidor, 5 = servidor desconhecido ou não informado,
+/// 6 = falha na negociação de opções
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about)]
 struct Args {
-   /// Name of the person to greet
+   /// Nome ou endereço do servidor TFTP
    #[arg(short, long)]
-   server: String,
+   server: Option<String>,
 
-   /// Number of times to greet
+   /// Port do servidor
    #[arg(short, long, default_value_t = 69)]
    port: u16,
+
+   /// Modo de transferência
+   #[arg(short, long, value_enum, default_value_t = Modo::Octet, global = true)]
+   mode: Modo,
+
+   /// Timeout de retransmissão, em segundos (negociado com o servidor)
+   #[arg(short, long, value_parser = clap::value_parser!(u16).range(1..=255), global = true)]
+   timeout: Option<u16>,
+
+   /// Número máximo de retransmissões
+   #[arg(short, long, default_value_t = 3, global = true)]
+   retries: u16,
+
+   /// Tamanho de bloco, em bytes (negociado com o servidor)
+   #[arg(short, long, value_parser = clap::value_parser!(u16).range(8..=65464), global = true)]
+   blksize: Option<u16>,
+
+   /// Quantidade de blocos enviados antes de esperar um ACK (negociado com o servidor)
+   #[arg(short, long, value_parser = clap::value_parser!(u16).range(1..), global = true)]
+   windowsize: Option<u16>,
+
+   /// Mostra o andamento da transferência e mais logs (-vv mostra cada bloco)
+   #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+   verbose: u8,
+
+   /// Mostra nos logs apenas erros
+   #[arg(short, long, conflicts_with = "verbose", global = true)]
+   quiet: bool,
+
+   /// Gera os logs em JSON
+   #[arg(long, global = true)]
+   log_json: bool,
+
+   #[command(subcommand)]
+   comando: Option<Comando>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Comando {
+   /// Recebe um arquivo do servidor
+   Get {
+      /// Nome do arquivo no servidor
+      remote: String,
+
+      /// Nome do arquivo local (por default, o mesmo nome do arquivo remoto)
+      local: Option<String>,
+   },
+   /// Envia um arquivo para o servidor
+   Put {
+      /// Nome do arquivo local
+      local: String,
+
+      /// Nome do arquivo no servidor (por default, o mesmo nome do arquivo local)
+      remote: Option<String>,
+   },
+}
+
+impl Comando {
+   /// mensagem mostrada quando o comando termina com sucesso
+   fn sucesso(&self, stats: &TransferStats) -> String {
+      let feito = match self {
+         Comando::Get { .. } => "Arquivo recebido e gravado",
+         Comando::Put { .. } => "Arquivo enviado",
+      };
+      format!("{} ({} bytes em {:.1} s)", feito, stats.bytes, stats.duracao.as_secs_f64())
+   }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Modo {
+   Octet,
+   Netascii,
+}
+
+/// Mostra o andamento das transferências, conforme a verbosidade
+struct Progresso {
+   verbose: u8,
+}
+
+impl SessionObserver for Progresso {
+   fn request_sent(&self, fname: &str, leitura: bool) {
+      eprintln!("{} {}", if leitura { "RRQ" } else { "WRQ" }, fname);
+   }
+
+   fn negotiated(&self, opcoes: &[(String, String)]) {
+      let opcoes: Vec<String> = opcoes.iter().map(|(nome, valor)| format!("{}={}", nome, valor)).collect();
+      eprintln!("opções aceitas: {}", opcoes.join(" "));
+   }
+
+   fn block_sent(&self, block: u16, len: usize) {
+      if self.verbose > 1 {
+         eprintln!("DATA {} enviado ({} bytes)", block, len);
+      }
+   }
+
+   fn block_acked(&self, block: u16) {
+      if self.verbose > 1 {
+         eprintln!("ACK {}", block);
+      }
+   }
+
+   fn block_received(&self, block: u16, len: usize) {
+      if self.verbose > 1 {
+         eprintln!("DATA {} recebido ({} bytes)", block, len);
+      }
+   }
+
+   fn retransmitted(&self, block: u16, tentativa: u16) {
+      eprintln!("retransmitindo a partir do bloco {} (tentativa {})", block, tentativa);
+   }
+}
+
+/// o nome de arquivo em um caminho, usado quando o outro nome é omitido
+fn basename(path: &str) -> String {
+   Path::new(path).file_name().map_or(path.to_owned(), |nome| nome.to_string_lossy().into_owned())
+}
+
+/// Configuração do cliente, mantida entre transferências
+#[derive(Debug)]
+pub struct Config {
+   server: Option<String>,
+   port: u16,
+   modo: msg::Modo,
+   timeout: Option<u16>,
+   retries: u16,
+   blksize: Option<u16>,
+   windowsize: Option<u16>,
+   verbose: u8,
+}
+
+impl Config {
+   fn new(args: &Args) -> Self {
+      Config {
+         server: args.server.clone(),
+         port: args.port,
+         modo: match args.mode {
+            Modo::Octet => msg::Modo::Octet,
+            Modo::Netascii => msg::Modo::Netascii,
+         },
+         timeout: args.timeout,
+         retries: args.retries,
+         blksize: args.blksize,
+         windowsize: args.windowsize,
+         verbose: args.verbose,
+      }
+   }
+
+   /// cria um cliente com esta configuração, se o servidor já foi informado
+   fn cliente(&self) -> Option<ClienteTFTP> {
+      let mut cliente = ClienteTFTP::new(self.server.as_ref()?, self.port);
+      cliente.set_modo(self.modo);
+      cliente.set_retries(self.retries);
+      if let Some(timeout) = self.timeout {
+         cliente.set_timeout(timeout);
+      }
+      if let Some(blksize) = self.blksize {
+         cliente.set_blksize(blksize as usize);
+      }
+      if let Some(windowsize) = self.windowsize {
+         cliente.set_windowsize(windowsize);
+      }
+      if self.verbose > 0 {
+         cliente.set_observer(Arc::new(Progresso { verbose: self.verbose }));
+      }
+      Some(cliente)
+   }
+
+   /// executa um comando get ou put, mostrando o erro, se houver.
+   /// Sem servidor, nada é executado
+   async fn executa(&self, comando: &Comando) -> Option<Result<TransferStats, TftpError>> {
+      let cliente = match self.cliente() {
+         Some(cliente) => cliente,
+         None => {
+            eprintln!("Servidor não informado");
+            return None;
+         }
+      };
+      let resultado = match comando {
+         Comando::Get { remote, local } => {
+            let local = local.clone().unwrap_or_else(|| basename(remote));
+            cliente.get(remote, &local).await
+         }
+         Comando::Put { local, remote } => {
+            let remote = remote.clone().unwrap_or_else(|| basename(local));
+            cliente.put(local, &remote).await
+         }
+      };
+      match &resultado {
+         Ok(_) => {}
+         Err(TftpError::Resolution(server)) => eprintln!("Servidor desconhecido: {}", server),
+         Err(TftpError::Io(e)) => eprintln!("Erro local: {}", e),
+         Err(TftpError::Protocol(texto)) => eprintln!("Erro de protocolo: {}", texto),
+         Err(TftpError::Peer(code, texto)) => eprintln!("Erro {}: {}", u16::from(*code), texto),
+         Err(TftpError::Timeout) => eprintln!("Timeout"),
+         Err(TftpError::Negotiation(texto)) => eprintln!("Falha na negociação de opções: {}", texto),
+      }
+      Some(resultado)
+   }
+}
+
+/// configura os logs, que vão para stderr. RUST_LOG, se definida, tem precedência
+fn inicia_logs(args: &Args) {
+   let nivel = if args.quiet { "error" } else { ["warn", "info", "debug", "trace"][args.verbose.min(3) as usize] };
+   tftp::inicia_logs(nivel, args.log_json);
 }
 
-fn main() {
+#[tokio::main]
+async fn main() -> ExitCode {
    let args = Args::parse();
-   let cliente = ClienteTFTP::new(&args.server, args.port);
-   match cliente.recebe("teste", "teste") {
-      Status::OK => println!("Arquivo recebido e gravado"),
-      Status::Error(e) => println!("Erro: {:?}", e),
-      Status::Unknown => println!("Erro desconhecido"),
-      Status::Timeout => println!("Timeout"),
-      Status::MaxRetriesExceeded => println!("retransmissões excedidas")
-   }
-}
\ No newline at end of file
+   inicia_logs(&args);
+   let config = Config::new(&args);
+   let comando = match &args.comando {
+      Some(comando) => comando,
+      None => {
+         shell::executa(config).await;
+         return ExitCode::SUCCESS;
+      }
+   };
+   let resultado = match config.executa(comando).await {
+      Some(resultado) => resultado,
+      None => return ExitCode::from(5),
+   };
+   match resultado {
+      Ok(stats) => {
+         if config.verbose > 0 {
+            println!("{}", comando.sucesso(&stats));
+         }
+         ExitCode::SUCCESS
+      }
+      Err(TftpError::Io(_)) => ExitCode::from(1),
+      Err(TftpError::Timeout) => ExitCode::from(2),
+      Err(TftpError::Protocol(_)) => ExitCode::from(3),
+      Err(TftpError::Peer(..)) => ExitCode::from(4),
+      Err(TftpError::Resolution(_)) => ExitCode::from(5),
+      Err(TftpError::Negotiation(_)) => ExitCode::from(6),
+   }
+}
@@ -1,3 +1,4 @@
+#![allow(clippy::upper_case_acronyms)]
 use std::fmt;
 
 pub enum Mensagem {
@@ -5,17 +6,54 @@ pub enum Mensagem {
     Rrq(Requisicao),
     Err(ERR),
     Data(DATA),
-    Ack(ACK)
+    Ack(ACK),
+    Oack(OACK)
 }
 
-fn get_shortint(buffer: &[u8]) -> u16 {
-    u16::from_be_bytes([buffer[0], buffer[1]])
+// Erros na decodificação de uma mensagem recebida
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    // a mensagem é menor que seus campos de tamanho fixo
+    Truncated,
+    UnknownOpcode(u16),
+    // um campo de texto não termina com NUL
+    MissingNul,
+    BadMode(String),
+    InvalidOption(String)
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "Truncated packet"),
+            DecodeError::UnknownOpcode(opcode) => write!(f, "Unknown opcode {}", opcode),
+            DecodeError::MissingNul => write!(f, "Missing NUL terminator"),
+            DecodeError::BadMode(modo) => write!(f, "Bad transfer mode \"{}\"", modo),
+            DecodeError::InvalidOption(nome) => write!(f, "Invalid option \"{}\"", nome)
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn get_shortint(buffer: &[u8]) -> Result<u16, DecodeError> {
+    match buffer {
+        [a, b, ..] => Ok(u16::from_be_bytes([*a, *b])),
+        _ => Err(DecodeError::Truncated)
+    }
+}
+
+// confere o opcode de uma mensagem
+fn check_opcode(buffer: &[u8], code: u16) -> Result<(), DecodeError> {
+    match get_shortint(buffer)? {
+        opcode if opcode == code => Ok(()),
+        opcode => Err(DecodeError::UnknownOpcode(opcode))
+    }
 }
 
 pub trait Codec {
     fn init(&self, code: u16) -> bytes::BytesMut {
-        let buffer = bytes::BytesMut::from(code.to_be_bytes().as_ref());
-        buffer
+        bytes::BytesMut::from(code.to_be_bytes().as_ref())
     }
 
     fn serialize(&self) -> bytes::BytesMut;    
@@ -36,7 +74,7 @@ impl TipoReq {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Modo {
     Netascii,
     Octet,
@@ -44,14 +82,6 @@ pub enum Modo {
 }
 
 impl Modo {
-    fn as_str0(&self) -> String {
-        String::from(match self {
-            Modo::Mail => "mail",
-            Modo::Netascii => "netascii",
-            Modo::Octet => "octet"
-        })
-    }
-
     fn as_str(&self) -> &str {
         match self {
             Modo::Mail => "mail",
@@ -64,7 +94,9 @@ impl Modo {
 pub struct Requisicao {
     pub fname: String,
     pub modo: Modo,
-    pub tipo: TipoReq
+    pub tipo: TipoReq,
+    // opções (RFC 2347), como pares (nome, valor), na ordem em que aparecem
+    pub opcoes: Vec<(String, String)>
 }
 
 // Mensagem de dados
@@ -78,12 +110,32 @@ pub struct ACK {
     pub block: u16
 }
 
+// Códigos de erro (RFC 1350 e RFC 2347). Códigos desconhecidos são mantidos em Other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NotDefined,
+    FileNotFound,
+    AccessViolation,
+    DiskFull,
+    IllegalOperation,
+    UnknownTid,
+    FileExists,
+    NoSuchUser,
+    OptionRejected,
+    Other(u16)
+}
+
 // Mensagem de erro
 pub struct ERR {
-    pub err_code: u16,
+    pub err_code: ErrorCode,
     pub err_msg: String
 }
 
+// Mensagem de confirmação de opções (RFC 2347)
+pub struct OACK {
+    pub opcoes: Vec<(String, String)>
+}
+
 
 // Implementação do trait Codec
 impl Codec for Requisicao {
@@ -93,6 +145,15 @@ impl Codec for Requisicao {
         buffer.extend(&[0]);
         buffer.extend(self.modo.as_str().as_bytes());
         buffer.extend(&[0]);
+        put_opcoes(&mut buffer, &self.opcoes);
+        buffer
+    }
+}
+
+impl Codec for OACK {
+    fn serialize(&self) -> bytes::BytesMut {
+        let mut buffer = self.init(OACK::CODE);
+        put_opcoes(&mut buffer, &self.opcoes);
         buffer
     }
 }
@@ -116,47 +177,78 @@ impl Codec for DATA {
 
 impl Codec for ERR {
     fn serialize(&self) ->bytes::BytesMut {
-        let mut buffer = self.init(DATA::CODE);
-        buffer.extend(self.err_code.to_be_bytes());
+        let mut buffer = self.init(ERR::CODE);
+        buffer.extend(u16::from(self.err_code).to_be_bytes());
         buffer.extend(self.err_msg.as_bytes());
         buffer.extend(&[0]);
         buffer
     }
 }
 
-fn get_string(buffer: &[u8]) -> String {
-    let sub:Vec<u8> = buffer.into_iter()
-                            .take_while(|x| **x != 0)
-                            .map(|x| *x).collect();
-    String::from_utf8_lossy(&sub).into_owned()
+// lê um texto terminado por NUL, e retorna também o que vem depois dele
+fn get_string(buffer: &[u8]) -> Result<(String, &[u8]), DecodeError> {
+    match buffer.iter().position(|x| *x == 0) {
+        Some(fim) => Ok((String::from_utf8_lossy(&buffer[..fim]).into_owned(), &buffer[fim+1..])),
+        None => Err(DecodeError::MissingNul)
+    }
+}
+
+// lê uma sequência de opções "nome\0valor\0"; nomes de opções não
+// diferenciam maiúsculas e minúsculas, por isso são guardados em minúsculas
+fn get_opcoes(mut buffer: &[u8]) -> Result<Vec<(String, String)>, DecodeError> {
+    let mut opcoes: Vec<(String, String)> = vec![];
+    while ! buffer.is_empty() {
+        let (nome, resto) = get_string(buffer)?;
+        let nome = nome.to_lowercase();
+        // um nome sem valor, vazio ou repetido
+        let (valor, resto) = get_string(resto)
+                                 .map_err(|_| DecodeError::InvalidOption(nome.clone()))?;
+        if nome.is_empty() || opcoes.iter().any(|(outro, _)| *outro == nome) {
+            return Err(DecodeError::InvalidOption(nome));
+        }
+        opcoes.push((nome, valor));
+        buffer = resto;
+    }
+    Ok(opcoes)
+}
+
+fn put_opcoes(buffer: &mut bytes::BytesMut, opcoes: &[(String, String)]) {
+    for (nome, valor) in opcoes {
+        buffer.extend(nome.as_bytes());
+        buffer.extend(&[0]);
+        buffer.extend(valor.as_bytes());
+        buffer.extend(&[0]);
+    }
 }
 
 impl Requisicao {
     const CODE_RRQ:u16 = 1;
     const CODE_WRQ:u16 = 2;
 
-    pub fn from_bytes(buffer: Vec<u8>) -> Option<Self> {
-        let opcode:u16 = get_shortint(&buffer);
+    pub fn from_bytes(buffer: Vec<u8>) -> Result<Self, DecodeError> {
+        let opcode:u16 = get_shortint(&buffer)?;
         let tipo = match opcode {
             Requisicao::CODE_RRQ => TipoReq::RRQ,
             Requisicao::CODE_WRQ => TipoReq::WRQ,
             _ => {
-                return None;
+                return Err(DecodeError::UnknownOpcode(opcode));
             }
         };
-        let name = get_string(&buffer[2..]);
-        let modo = get_string(&buffer[name.len()+3..]);
-        Some(Requisicao{
+        let (name, resto) = get_string(&buffer[2..])?;
+        let (modo, resto) = get_string(resto)?;
+        let opcoes = get_opcoes(resto)?;
+        Ok(Requisicao{
             fname: name, 
-            modo: match modo.as_str() {
+            modo: match modo.to_lowercase().as_str() {
                 "octet" => Modo::Octet,
                 "netascii" => Modo::Netascii,
                 "mail" => Modo::Mail,
                 _ => {
-                    panic!("modo inválido");
+                    return Err(DecodeError::BadMode(modo));
                 }
             },
-            tipo: tipo
+            tipo,
+            opcoes
         })
     }
 
@@ -166,8 +258,9 @@ impl Requisicao {
         }
         Some(Requisicao {
             fname: fname.to_owned(),
-            modo: modo,
-            tipo: tipo
+            modo,
+            tipo,
+            opcoes: vec![]
         })
     }
 
@@ -179,13 +272,11 @@ impl Requisicao {
     }
 
     pub fn is_rrq(buffer: &[u8]) -> bool {
-        let opcode:u16 = get_shortint(&buffer);
-        opcode == Requisicao::CODE_RRQ
+        get_shortint(buffer) == Ok(Requisicao::CODE_RRQ)
     }
 
     pub fn is_wrq(buffer: &[u8]) -> bool {
-        let opcode:u16 = get_shortint(&buffer);
-        opcode == Requisicao::CODE_WRQ
+        get_shortint(buffer) == Ok(Requisicao::CODE_WRQ)
     }
 
 }
@@ -193,23 +284,21 @@ impl Requisicao {
 impl DATA {
     const CODE:u16 = 3;
     pub const SIZE:usize = 512;
-
-    pub fn from_bytes(buffer: Vec<u8>) -> Option<Self> {
-        let opcode:u16 = get_shortint(&buffer);
-        if opcode != DATA::CODE {
-            return None;
-        }
-        let blocknum = get_shortint(&buffer[2..]);
-        Some(DATA {
+    // limites da opção blksize (RFC 2348)
+    pub const MIN_SIZE:usize = 8;
+    pub const MAX_SIZE:usize = 65464;
+
+    pub fn from_bytes(buffer: Vec<u8>) -> Result<Self, DecodeError> {
+        check_opcode(&buffer, DATA::CODE)?;
+        let blocknum = get_shortint(&buffer[2..])?;
+        Ok(DATA {
             block: blocknum,
             body: buffer[4..].to_vec()
         })
     }
 
+    // o bloco 0 é válido: é o que segue o bloco 65535, se os números recomeçam de 0
     pub fn new(blocknum: u16, buffer: &[u8]) -> Option<Self> {
-        if blocknum < 1 {
-            return None;
-        }
         Some(DATA {
             block: blocknum,
             body: buffer.to_vec()
@@ -217,36 +306,29 @@ impl DATA {
     }
 
     pub fn is_data(buffer: &[u8]) -> bool {
-        let opcode:u16 = get_shortint(&buffer);
-        opcode == DATA::CODE    
+        get_shortint(buffer) == Ok(DATA::CODE)
     }
 }
 
 impl ACK {
     const CODE:u16 = 4;
 
-    pub fn from_bytes(buffer: Vec<u8>) -> Option<Self> {
-        let opcode:u16 = get_shortint(&buffer);
-        if opcode != ACK::CODE {
-            return None;
-        }
-        let blocknum = get_shortint(&buffer[2..]);
-        Some(ACK {
+    pub fn from_bytes(buffer: Vec<u8>) -> Result<Self, DecodeError> {
+        check_opcode(&buffer, ACK::CODE)?;
+        let blocknum = get_shortint(&buffer[2..])?;
+        Ok(ACK {
             block: blocknum,
         })
     }
+    // o bloco 0 é válido: é a resposta do servidor a um WRQ
     pub fn new(blocknum: u16) -> Option<Self> {
-        if blocknum < 1 {
-            return None;
-        }
         Some(ACK {
             block: blocknum,
         })
     }   
 
     pub fn is_ack(buffer: &[u8]) -> bool {
-        let opcode:u16 = get_shortint(&buffer);
-        opcode == ACK::CODE    
+        get_shortint(buffer) == Ok(ACK::CODE)
     }
 
 }
@@ -254,44 +336,75 @@ impl ACK {
 impl ERR {
     const CODE:u16 = 5;
 
-    pub fn from_bytes(buffer: Vec<u8>) -> Option<Self> {
-        let opcode:u16 = get_shortint(&buffer);
-        if opcode != ERR::CODE {
-            return None
-        }
-        let err_code = get_shortint(&buffer[2..]);
+    pub fn from_bytes(buffer: Vec<u8>) -> Result<Self, DecodeError> {
+        check_opcode(&buffer, ERR::CODE)?;
+        let err_code = ErrorCode::from(get_shortint(&buffer[2..])?);
 
-        let err_msg = get_string(&buffer[4..]);
-        Some(ERR{
-            err_code: err_code,
-            err_msg: err_msg
+        // alguns servidores não terminam a mensagem com NUL: ela é aceita mesmo assim
+        let err_msg = match get_string(&buffer[4..]) {
+            Ok((err_msg, _)) => err_msg,
+            Err(_) => String::from_utf8_lossy(&buffer[4..]).into_owned()
+        };
+        Ok(ERR{
+            err_code,
+            err_msg
         })
     }
 
-    pub fn new(err_code: u16, err_msg: &str) -> Option<Self> {
+    pub fn new(err_code: ErrorCode, err_msg: &str) -> Option<Self> {
         Some(ERR {
-            err_code: err_code,
+            err_code,
             err_msg: err_msg.to_owned()
         })
     }   
 
     pub fn is_err(buffer: &[u8]) -> bool {
-        let opcode:u16 = get_shortint(&buffer);
-        opcode == ERR::CODE    
+        get_shortint(buffer) == Ok(ERR::CODE)
+    }
+
+}
+
+impl OACK {
+    const CODE:u16 = 6;
+
+    pub fn from_bytes(buffer: Vec<u8>) -> Result<Self, DecodeError> {
+        check_opcode(&buffer, OACK::CODE)?;
+        let opcoes = get_opcoes(&buffer[2..])?;
+        if opcoes.is_empty() {
+            return Err(DecodeError::Truncated);
+        }
+        Ok(OACK {
+            opcoes
+        })
+    }
+
+    // um OACK sem opções não faz sentido: o servidor teria respondido com ACK ou DATA
+    pub fn new(opcoes: Vec<(String, String)>) -> Option<Self> {
+        if opcoes.is_empty() {
+            return None;
+        }
+        Some(OACK {
+            opcoes
+        })
+    }
+
+    pub fn is_oack(buffer: &[u8]) -> bool {
+        get_shortint(buffer) == Ok(OACK::CODE)
     }
 
 }
 
 /// A factory function to build a TFTP message from a vector of bytes
-pub fn from_bytes(buffer: Vec<u8>) -> Option<Mensagem> {
-    let opcode:u16 = get_shortint(&buffer);
+pub fn from_bytes(buffer: Vec<u8>) -> Result<Mensagem, DecodeError> {
+    let opcode:u16 = get_shortint(&buffer)?;
     match opcode {
-        Requisicao::CODE_RRQ => Requisicao::from_bytes(buffer).and_then(|m| Some(Mensagem::Rrq(m))),
-        Requisicao::CODE_WRQ => Requisicao::from_bytes(buffer).and_then(|m| Some(Mensagem::Wrq(m))),
-        DATA::CODE => DATA::from_bytes(buffer).and_then(|m| Some(Mensagem::Data(m))),
-        ACK::CODE => ACK::from_bytes(buffer).and_then(|m| Some(Mensagem::Ack(m))),
-        ERR::CODE => ERR::from_bytes(buffer).and_then(|m| Some(Mensagem::Err(m))),
-        _ => None
+        Requisicao::CODE_RRQ => Requisicao::from_bytes(buffer).map(Mensagem::Rrq),
+        Requisicao::CODE_WRQ => Requisicao::from_bytes(buffer).map(Mensagem::Wrq),
+        DATA::CODE => DATA::from_bytes(buffer).map(Mensagem::Data),
+        ACK::CODE => ACK::from_bytes(buffer).map(Mensagem::Ack),
+        ERR::CODE => ERR::from_bytes(buffer).map(Mensagem::Err),
+        OACK::CODE => OACK::from_bytes(buffer).map(Mensagem::Oack),
+        _ => Err(DecodeError::UnknownOpcode(opcode))
     }
 }
 
@@ -305,7 +418,7 @@ impl fmt::Display for Requisicao {
                 "WRQ"
             }
         };
-        write!(f, "{}: filename={}, modo={:?}", tipo, self.fname, self.modo)
+        write!(f, "{}: filename={}, modo={:?}, opcoes={:?}", tipo, self.fname, self.modo, self.opcoes)
     }    
 }
 
@@ -323,7 +436,65 @@ impl fmt::Display for ACK {
 
 impl fmt::Display for ERR {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Err: err_code={}, err_msg={}", self.err_code, self.err_msg)
+        write!(f, "Err: err_code={}, err_msg={}", u16::from(self.err_code), self.err_msg)
+    }    
+}
+
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        match code {
+            0 => ErrorCode::NotDefined,
+            1 => ErrorCode::FileNotFound,
+            2 => ErrorCode::AccessViolation,
+            3 => ErrorCode::DiskFull,
+            4 => ErrorCode::IllegalOperation,
+            5 => ErrorCode::UnknownTid,
+            6 => ErrorCode::FileExists,
+            7 => ErrorCode::NoSuchUser,
+            8 => ErrorCode::OptionRejected,
+            code => ErrorCode::Other(code)
+        }
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::NotDefined => 0,
+            ErrorCode::FileNotFound => 1,
+            ErrorCode::AccessViolation => 2,
+            ErrorCode::DiskFull => 3,
+            ErrorCode::IllegalOperation => 4,
+            ErrorCode::UnknownTid => 5,
+            ErrorCode::FileExists => 6,
+            ErrorCode::NoSuchUser => 7,
+            ErrorCode::OptionRejected => 8,
+            ErrorCode::Other(code) => code
+        }
+    }
+}
+
+// a descrição de cada código, como na RFC 1350
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorCode::NotDefined => write!(f, "Not defined"),
+            ErrorCode::FileNotFound => write!(f, "File not found"),
+            ErrorCode::AccessViolation => write!(f, "Access violation"),
+            ErrorCode::DiskFull => write!(f, "Disk full or allocation exceeded"),
+            ErrorCode::IllegalOperation => write!(f, "Illegal TFTP operation"),
+            ErrorCode::UnknownTid => write!(f, "Unknown transfer ID"),
+            ErrorCode::FileExists => write!(f, "File already exists"),
+            ErrorCode::NoSuchUser => write!(f, "No such user"),
+            ErrorCode::OptionRejected => write!(f, "Option negotiation failed"),
+            ErrorCode::Other(code) => write!(f, "Error {}", code)
+        }
+    }
+}
+
+impl fmt::Display for OACK {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OACK: opcoes={:?}", self.opcoes)
     }    
 }
 
@@ -334,7 +505,72 @@ impl fmt::Display for Mensagem {
             Mensagem::Wrq(msg) => write!(f, "{}", msg),
             Mensagem::Data(msg) => write!(f, "{}", msg),
             Mensagem::Ack(msg) => write!(f, "{}", msg),
-            Mensagem::Err(msg) => write!(f, "{}", msg)
+            Mensagem::Err(msg) => write!(f, "{}", msg),
+            Mensagem::Oack(msg) => write!(f, "{}", msg)
         }
     }    
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // o erro na decodificação de "pkt"
+    fn erro(pkt: &[u8]) -> DecodeError {
+        from_bytes(pkt.to_vec()).err().expect("malformed packet was decoded")
+    }
+
+    #[test]
+    fn short_packet() {
+        assert_eq!(erro(b"\0"), DecodeError::Truncated);
+        assert_eq!(erro(b"\0\x03\0"), DecodeError::Truncated);
+        assert_eq!(erro(b"\0\x04\0"), DecodeError::Truncated);
+        assert_eq!(erro(b"\0\x05\0"), DecodeError::Truncated);
+        // OACK sem nenhuma opção
+        assert_eq!(erro(b"\0\x06"), DecodeError::Truncated);
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        assert_eq!(erro(b"\0\x09\0\x01"), DecodeError::UnknownOpcode(9));
+    }
+
+    #[test]
+    fn missing_nul() {
+        assert_eq!(erro(b"\0\x01arquivo"), DecodeError::MissingNul);
+        assert_eq!(erro(b"\0\x02arquivo\0octet"), DecodeError::MissingNul);
+    }
+
+    #[test]
+    fn unknown_mode() {
+        assert_eq!(erro(b"\0\x01arquivo\0binario\0"), DecodeError::BadMode(String::from("binario")));
+    }
+
+    #[test]
+    fn duplicate_option() {
+        // nomes de opções não diferenciam maiúsculas e minúsculas
+        assert_eq!(erro(b"\0\x01arquivo\0octet\0blksize\x001024\0BLKSIZE\x00512\0"),
+                   DecodeError::InvalidOption(String::from("blksize")));
+        assert_eq!(erro(b"\0\x06tsize\x000\0tsize\x001\0"), DecodeError::InvalidOption(String::from("tsize")));
+    }
+
+    #[test]
+    fn odd_option_list() {
+        // um nome sem valor
+        assert_eq!(erro(b"\0\x01arquivo\0octet\0blksize\0"), DecodeError::InvalidOption(String::from("blksize")));
+        assert_eq!(erro(b"\0\x06tsize\0"), DecodeError::InvalidOption(String::from("tsize")));
+        // um nome vazio
+        assert_eq!(erro(b"\0\x01arquivo\0octet\0\x00512\0"), DecodeError::InvalidOption(String::new()));
+    }
+
+    #[test]
+    fn valid_request() {
+        match from_bytes(b"\0\x01arquivo\0NetASCII\0BlkSize\x001024\0".to_vec()) {
+            Ok(Mensagem::Rrq(req)) => {
+                assert_eq!(req.fname, "arquivo");
+                assert_eq!(req.modo, Modo::Netascii);
+                assert_eq!(req.opcoes, [(String::from("blksize"), String::from("1024"))]);
+            }
+            _ => panic!("expected RRQ")
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// Conversão de e para o modo netascii (RFC 1350 e RFC 764): na rede, fim de linha
+// é CR LF, e um CR isolado é CR NUL. Localmente, fim de linha é LF (ou CR LF no Windows).
+// Codificador e decodificador guardam estado entre blocos, porque um CR pode terminar
+// um bloco e o LF (ou NUL) que o acompanha estar no bloco seguinte.
+
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+const NUL: u8 = 0;
+
+// fim de linha local
+const EOL: &[u8] = if cfg!(windows) { b"\r\n" } else { b"\n" };
+
+// Codifica conteúdo local em netascii
+#[derive(Debug, Default)]
+pub struct Encoder {
+    // o último byte lido foi um CR, ainda não codificado
+    cr: bool
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder::default()
+    }
+
+    pub fn encode(&mut self, buffer: &[u8]) -> Vec<u8> {
+        let mut saida = Vec::with_capacity(buffer.len() + buffer.len() / 16);
+        for &c in buffer {
+            if self.cr {
+                self.cr = false;
+                if c == LF {
+                    // CR LF local (Windows) já é um fim de linha netascii
+                    saida.extend([CR, LF]);
+                    continue;
+                }
+                saida.extend([CR, NUL]);
+            }
+            match c {
+                CR if EOL.len() > 1 => self.cr = true,
+                CR => saida.extend([CR, NUL]),
+                LF => saida.extend([CR, LF]),
+                _ => saida.push(c)
+            }
+        }
+        saida
+    }
+
+    // codifica o que ficou pendente no fim do conteúdo
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut saida = vec![];
+        if self.cr {
+            self.cr = false;
+            saida.extend([CR, NUL]);
+        }
+        saida
+    }
+}
+
+// Decodifica conteúdo netascii para o formato local
+#[derive(Debug, Default)]
+pub struct Decoder {
+    // o último byte recebido foi um CR, ainda não decodificado
+    cr: bool
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder::default()
+    }
+
+    pub fn decode(&mut self, buffer: &[u8]) -> Vec<u8> {
+        let mut saida = Vec::with_capacity(buffer.len());
+        for &c in buffer {
+            if self.cr {
+                self.cr = false;
+                match c {
+                    LF => {
+                        saida.extend(EOL);
+                        continue;
+                    }
+                    NUL => {
+                        saida.push(CR);
+                        continue;
+                    }
+                    // CR seguido de outra coisa não é netascii válido: o CR é mantido
+                    _ => saida.push(CR)
+                }
+            }
+            if c == CR {
+                self.cr = true;
+            } else {
+                saida.push(c);
+            }
+        }
+        saida
+    }
+
+    // decodifica o que ficou pendente no fim do conteúdo
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut saida = vec![];
+        if self.cr {
+            self.cr = false;
+            saida.push(CR);
+        }
+        saida
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // codifica "partes" como blocos lidos em sequência
+    fn codifica(partes: &[&[u8]]) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        let mut saida: Vec<u8> = partes.iter().flat_map(|p| encoder.encode(p)).collect();
+        saida.extend(encoder.finish());
+        saida
+    }
+
+    // decodifica "partes" como blocos recebidos em sequência
+    fn decodifica(partes: &[&[u8]]) -> Vec<u8> {
+        let mut decoder = Decoder::new();
+        let mut saida: Vec<u8> = partes.iter().flat_map(|p| decoder.decode(p)).collect();
+        saida.extend(decoder.finish());
+        saida
+    }
+
+    // todas as divisões de "buffer" em dois blocos
+    fn divisoes(buffer: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+        (0..=buffer.len()).map(move |i| buffer.split_at(i))
+    }
+
+    #[test]
+    fn encode_end_of_line() {
+        let local = [b"um", EOL, b"dois", EOL].concat();
+        for (a, b) in divisoes(&local) {
+            assert_eq!(codifica(&[a, b]), b"um\r\ndois\r\n", "blocos {:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn encode_lone_cr() {
+        for (a, b) in divisoes(b"um\rdois") {
+            assert_eq!(codifica(&[a, b]), b"um\r\0dois", "blocos {:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn encode_cr_at_buffer_end() {
+        assert_eq!(codifica(&[b"um\r", b"dois"]), b"um\r\0dois");
+        assert_eq!(codifica(&[b"um\r", b""]), b"um\r\0");
+        // no fim do conteúdo, o CR pendente sai em "finish"
+        assert_eq!(codifica(&[b"fim\r"]), b"fim\r\0");
+    }
+
+    #[test]
+    fn decode_crlf() {
+        let local = [b"um", EOL, b"dois", EOL].concat();
+        for (a, b) in divisoes(b"um\r\ndois\r\n") {
+            assert_eq!(decodifica(&[a, b]), local, "blocos {:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn decode_lone_cr() {
+        for (a, b) in divisoes(b"um\r\0dois") {
+            assert_eq!(decodifica(&[a, b]), b"um\rdois", "blocos {:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn decode_cr_at_buffer_end() {
+        assert_eq!(decodifica(&[b"um\r", b"\ndois"]), [b"um", EOL, b"dois"].concat());
+        assert_eq!(decodifica(&[b"um\r", b"\0dois"]), b"um\rdois");
+        // CR seguido de outra coisa, ou no fim do conteúdo, é mantido
+        assert_eq!(decodifica(&[b"um\r", b"dois"]), b"um\rdois");
+        assert_eq!(decodifica(&[b"fim\r"]), b"fim\r");
+    }
+}
@@ -0,0 +1,120 @@
+// Prompt interativo, no estilo do tftp(1) clássico. A configuração (servidor, modo,
+// timeouts, trace) é mantida entre os comandos.
+
+use std::io::Write;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tftp::msg;
+use crate::{Comando, Config};
+
+// intervalo de retransmissão usado pelo cliente quando o timeout não é negociado
+const REXMT_PADRAO: u16 = 1;
+
+const AJUDA: &str = "\
+connect host [port]   define o servidor
+mode [octet|netascii] define ou mostra o modo de transferência
+binary | ascii        o mesmo que mode octet e mode netascii
+get remoto [local]    recebe um arquivo
+put local [remoto]    envia um arquivo
+timeout segundos      tempo total de espera por uma resposta
+rexmt segundos        intervalo de retransmissão (negociado com o servidor)
+trace                 liga ou desliga a exibição de cada bloco
+status                mostra a configuração atual
+quit                  termina
+?                     mostra esta ajuda";
+
+// intervalo de retransmissão em uso
+fn rexmt(config: &Config) -> u16 {
+   config.timeout.unwrap_or(REXMT_PADRAO)
+}
+
+fn nome_modo(modo: msg::Modo) -> &'static str {
+   match modo {
+      msg::Modo::Netascii => "netascii",
+      _ => "octet",
+   }
+}
+
+fn mostra_status(config: &Config) {
+   match &config.server {
+      Some(server) => println!("Conectado a {} port {}", server, config.port),
+      None => println!("Não conectado"),
+   }
+   println!("Modo: {}  Trace: {}", nome_modo(config.modo), if config.verbose > 1 { "ligado" } else { "desligado" });
+   // o intervalo dobra a cada retransmissão, e se adapta ao RTT medido: o tempo
+   // total de espera não é um múltiplo fixo dele
+   println!("Intervalo de retransmissão: {} s, retransmissões: {}", rexmt(config), config.retries);
+}
+
+// lê um número de segundos, entre 1 e 255
+fn segundos(valor: &str) -> Option<u16> {
+   match valor.parse::<u16>() {
+      Ok(n) if (1..=255).contains(&n) => Some(n),
+      _ => {
+         eprintln!("Valor inválido: {} (deve estar entre 1 e 255)", valor);
+         None
+      }
+   }
+}
+
+async fn transfere(config: &Config, comando: Comando) {
+   if let Some(Ok(stats)) = config.executa(&comando).await {
+      println!("{}", comando.sucesso(&stats));
+   }
+}
+
+/// executa o prompt interativo, até "quit" ou fim da entrada
+pub async fn executa(mut config: Config) {
+   let mut linhas = BufReader::new(tokio::io::stdin()).lines();
+   loop {
+      print!("tftp> ");
+      let _ = std::io::stdout().flush();
+      let linha = match linhas.next_line().await {
+         Ok(Some(linha)) => linha,
+         _ => break,
+      };
+      let palavras: Vec<&str> = linha.split_whitespace().collect();
+      match palavras.as_slice() {
+         [] => {}
+         ["connect", server] => config.server = Some(server.to_string()),
+         ["connect", server, port] => match port.parse() {
+            Ok(port) => {
+               config.server = Some(server.to_string());
+               config.port = port;
+            }
+            Err(_) => eprintln!("Port inválido: {}", port),
+         },
+         ["mode"] => println!("Modo: {}", nome_modo(config.modo)),
+         ["mode", "octet" | "binary"] | ["binary"] => config.modo = msg::Modo::Octet,
+         ["mode", "netascii" | "ascii"] | ["ascii"] => config.modo = msg::Modo::Netascii,
+         ["get", remote] => transfere(&config, Comando::Get { remote: remote.to_string(), local: None }).await,
+         ["get", remote, local] => {
+            transfere(&config, Comando::Get { remote: remote.to_string(), local: Some(local.to_string()) }).await
+         }
+         ["put", local] => transfere(&config, Comando::Put { local: local.to_string(), remote: None }).await,
+         ["put", local, remote] => {
+            transfere(&config, Comando::Put { local: local.to_string(), remote: Some(remote.to_string()) }).await
+         }
+         ["timeout", valor] => {
+            if let Some(total) = segundos(valor) {
+               config.retries = (total / rexmt(&config)).max(1) - 1;
+            }
+         }
+         ["rexmt", valor] => {
+            if let Some(rexmt) = segundos(valor) {
+               config.timeout = Some(rexmt);
+            }
+         }
+         ["trace"] => {
+            config.verbose = if config.verbose > 1 { 0 } else { 2 };
+            println!("Trace {}", if config.verbose > 1 { "ligado" } else { "desligado" });
+         }
+         ["status"] => mostra_status(&config),
+         ["quit" | "q"] => break,
+         ["?" | "help"] => println!("{}", AJUDA),
+         [comando, ..] => match AJUDA.lines().find(|uso| uso.starts_with(&format!("{} ", comando))) {
+            Some(uso) => eprintln!("Uso: {}", uso),
+            None => eprintln!("Comando inválido: {} (use ? para ajuda)", comando),
+         },
+      }
+   }
+}
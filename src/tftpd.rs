@@ -0,0 +1,42 @@
+use clap::Parser;
+use tftp::ServidorTFTP;
+
+/// Um pequeno servidor TFTP experimental
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+   /// Diretório de onde os arquivos são servidos
+   #[arg(short, long, default_value_t = String::from("."))]
+   dir: String,
+
+   /// Port UDP onde as requisições são recebidas
+   #[arg(short, long, default_value_t = ServidorTFTP::PORT)]
+   port: u16,
+
+   /// Mostra mais detalhes nos logs (-vv mostra cada bloco)
+   #[arg(short, long, action = clap::ArgAction::Count)]
+   verbose: u8,
+
+   /// Mostra nos logs apenas erros
+   #[arg(short, long, conflicts_with = "verbose")]
+   quiet: bool,
+
+   /// Gera os logs em JSON
+   #[arg(long)]
+   log_json: bool,
+}
+
+/// configura os logs, que vão para stderr. RUST_LOG, se definida, tem precedência
+fn inicia_logs(args: &Args) {
+   let nivel = if args.quiet { "error" } else { ["info", "debug", "trace"][args.verbose.min(2) as usize] };
+   tftp::inicia_logs(nivel, args.log_json);
+}
+
+fn main() {
+   let args = Args::parse();
+   inicia_logs(&args);
+   let servidor = ServidorTFTP::new(&args.dir, args.port);
+   if let Err(erro) = servidor.executa() {
+      tracing::error!(port = args.port, %erro, "não conseguiu usar o port");
+   }
+}
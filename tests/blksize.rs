@@ -0,0 +1,101 @@
+// Block size negotiation (RFC 2348): the server may only acknowledge a block size
+// between 8 and the requested one, and the negotiated size sets both how the file
+// is split into blocks and which block is the last one.
+
+mod common;
+
+use tftp::{ErrorCode, TftpError};
+use common::{ack, block, data, err, espera, oack, servidor_falso};
+
+const BLKSIZE: usize = 1024;
+
+// 2 full blocks and a last one of 512 bytes, which would be full with the default size
+fn conteudo() -> Vec<u8> {
+    (0..2 * BLKSIZE + 512).map(|i| i as u8).collect()
+}
+
+// the client requests 1024 bytes and the server acknowledges "blksize"
+async fn recusado(blksize: &str) {
+    let (sock, mut cliente) = servidor_falso().await;
+    assert!(cliente.set_blksize(BLKSIZE));
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&oack(&[("blksize", blksize)]), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        err(&buf[..n])
+    };
+    let (resultado, (code, _)) = tokio::join!(cliente.get_into("arquivo", Vec::new()), servidor);
+
+    assert_eq!(code, u16::from(ErrorCode::OptionRejected), "blksize {}", blksize);
+    assert!(matches!(resultado, Err(TftpError::Negotiation(_))), "blksize {}: {:?}", blksize, resultado);
+}
+
+#[tokio::test]
+async fn client_rejects_blksize_above_requested() {
+    recusado("2048").await;
+}
+
+#[tokio::test]
+async fn client_rejects_blksize_below_8() {
+    recusado("7").await;
+}
+
+#[tokio::test]
+async fn client_get_uses_negotiated_blksize() {
+    let (sock, mut cliente) = servidor_falso().await;
+    assert!(cliente.set_blksize(BLKSIZE));
+    let conteudo = conteudo();
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&oack(&[("blksize", "1024")]), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(0));
+        for (b, bloco) in conteudo.chunks(BLKSIZE).enumerate() {
+            let b = b as u16 + 1;
+            sock.send_to(&data(b, bloco), peer).await.unwrap();
+            let (n, _) = espera(&sock, &mut buf).await;
+            assert_eq!(&buf[..n], &ack(b), "expected ACK {}", b);
+        }
+    };
+    let mut recebido = vec![];
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", &mut recebido), servidor);
+
+    // the 512 bytes block ended the transfer
+    let stats = resultado.unwrap();
+    assert_eq!(stats.blocos, 3);
+    assert_eq!(recebido, conteudo);
+}
+
+#[tokio::test]
+async fn client_put_uses_negotiated_blksize() {
+    let (sock, mut cliente) = servidor_falso().await;
+    assert!(cliente.set_blksize(BLKSIZE));
+    let conteudo = conteudo();
+
+    let servidor = async {
+        let mut buf = [0u8; 2048];
+        let mut tamanhos = vec![];
+        let mut recebido = vec![];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&oack(&[("blksize", "1024")]), peer).await.unwrap();
+        loop {
+            let (n, _) = espera(&sock, &mut buf).await;
+            tamanhos.push(n - 4);
+            recebido.extend_from_slice(&buf[4..n]);
+            sock.send_to(&ack(block(&buf)), peer).await.unwrap();
+            if n - 4 < BLKSIZE {
+                return (tamanhos, recebido);
+            }
+        }
+    };
+    let (resultado, (tamanhos, recebido)) = tokio::join!(
+        cliente.put_from(&conteudo[..], "arquivo", None), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(tamanhos, [BLKSIZE, BLKSIZE, 512]);
+    assert_eq!(recebido, conteudo);
+}
@@ -0,0 +1,100 @@
+// Helpers shared by the integration tests: a scripted peer on a UDP socket,
+// packet builders and a local server. Not every test uses all of them.
+#![allow(dead_code)]
+
+use std::net::{SocketAddr, UdpSocket as StdUdpSocket};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+use tftp::{ClienteTFTP, ServidorTFTP};
+
+pub const BLKSIZE: usize = 512;
+
+pub fn data(block: u16, dados: &[u8]) -> Vec<u8> {
+    let mut pkt = vec![0, 3];
+    pkt.extend_from_slice(&block.to_be_bytes());
+    pkt.extend_from_slice(dados);
+    pkt
+}
+
+pub fn ack(block: u16) -> [u8; 4] {
+    let b = block.to_be_bytes();
+    [0, 4, b[0], b[1]]
+}
+
+// an OACK acknowledging "opcoes"
+pub fn oack(opcoes: &[(&str, &str)]) -> Vec<u8> {
+    let mut pkt = vec![0, 6];
+    for (nome, valor) in opcoes {
+        pkt.extend_from_slice(nome.as_bytes());
+        pkt.push(0);
+        pkt.extend_from_slice(valor.as_bytes());
+        pkt.push(0);
+    }
+    pkt
+}
+
+// the options in the RRQ, WRQ or OACK in "pkt", as (name, value) pairs
+pub fn opcoes(pkt: &[u8]) -> Vec<(String, String)> {
+    let mut campos = pkt[2..].split(|&b| b == 0).map(|campo| String::from_utf8_lossy(campo).into_owned());
+    if pkt[1] != 6 {
+        // skips the file name and the mode
+        campos.nth(1);
+    }
+    let campos: Vec<String> = campos.collect();
+    campos.chunks_exact(2).map(|par| (par[0].clone(), par[1].clone())).collect()
+}
+
+// the block number of a DATA or ACK in "pkt"
+pub fn block(pkt: &[u8]) -> u16 {
+    u16::from_be_bytes([pkt[2], pkt[3]])
+}
+
+// the ERR code and message in "pkt"
+pub fn err(pkt: &[u8]) -> (u16, String) {
+    assert_eq!(&pkt[..2], &[0, 5], "expected ERR, got {:?}", pkt);
+    let texto = pkt[4..].split(|&b| b == 0).next().unwrap();
+    (u16::from_be_bytes([pkt[2], pkt[3]]), String::from_utf8_lossy(texto).into_owned())
+}
+
+// waits for the next packet, failing if none arrives (e.g. the other side stopped
+// retransmitting)
+pub async fn espera(sock: &UdpSocket, buf: &mut [u8]) -> (usize, SocketAddr) {
+    timeout(Duration::from_secs(5), sock.recv_from(buf)).await
+        .expect("no packet arrived").unwrap()
+}
+
+// nothing arrives at "sock" for a while
+pub async fn nada(sock: &UdpSocket) {
+    let mut buf = [0u8; 1024];
+    if let Ok(Ok((n, addr))) = timeout(Duration::from_millis(300), sock.recv_from(&mut buf)).await {
+        panic!("unexpected packet from {}: {:?}", addr, &buf[..n]);
+    }
+}
+
+// a scripted server on the loopback, and a client that talks to it
+pub async fn servidor_falso() -> (UdpSocket, ClienteTFTP) {
+    let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let port = sock.local_addr().unwrap().port();
+    (sock, ClienteTFTP::new("127.0.0.1", port))
+}
+
+// a temporary directory "nome" where "arquivo" holds "conteudo"
+pub fn dir_local(nome: &str, conteudo: &[u8]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tftp-{}-{}", nome, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("arquivo"), conteudo).unwrap();
+    dir
+}
+
+// runs a ServidorTFTP on the loopback, serving a temporary directory "nome" where
+// "arquivo" holds "conteudo". Returns the directory and the server's port
+pub async fn servidor_real(nome: &str, conteudo: &[u8]) -> (PathBuf, u16) {
+    let dir = dir_local(nome, conteudo);
+    let port = StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let servidor = ServidorTFTP::new(dir.to_str().unwrap(), port);
+    std::thread::spawn(move || servidor.executa());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    (dir, port)
+}
@@ -0,0 +1,85 @@
+// When reading or writing the local contents fails, the peer must be told with an
+// ERR (instead of being left to time out), and the error must reach the caller.
+
+mod common;
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tftp::{ClienteTFTP, ErrorCode, TftpError};
+use common::{err, espera, servidor_falso};
+
+// contents that can't be read or written: every operation fails with "kind"
+struct Quebrado(io::ErrorKind);
+
+impl AsyncRead for Quebrado {
+    fn poll_read(self: Pin<&mut Self>, _: &mut Context<'_>, _: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(self.0.into()))
+    }
+}
+
+impl AsyncWrite for Quebrado {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(self.0.into()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn disk_full_is_reported_to_the_server() {
+    let (sock, cliente) = servidor_falso().await;
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(b"\0\x03\0\x01dados", peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        err(&buf[..n])
+    };
+    let (resultado, (code, _)) = tokio::join!(
+        cliente.get_into("arquivo", Quebrado(io::ErrorKind::StorageFull)), servidor);
+
+    assert_eq!(code, u16::from(ErrorCode::DiskFull));
+    match resultado {
+        Err(TftpError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::StorageFull),
+        _ => panic!("resultado: {:?}", resultado),
+    }
+}
+
+#[tokio::test]
+async fn read_error_is_reported_to_the_server() {
+    let (sock, cliente) = servidor_falso().await;
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(b"\0\x04\0\0", peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        err(&buf[..n])
+    };
+    let (resultado, (code, texto)) = tokio::join!(
+        cliente.put_from(Quebrado(io::ErrorKind::BrokenPipe), "arquivo", None), servidor);
+
+    assert_eq!(code, u16::from(ErrorCode::NotDefined));
+    assert!(!texto.is_empty());
+    assert!(matches!(resultado, Err(TftpError::Io(_))), "resultado: {:?}", resultado);
+}
+
+#[tokio::test]
+async fn missing_local_file_is_reported_to_the_caller() {
+    let cliente = ClienteTFTP::new("127.0.0.1", 9);
+    let resultado = cliente.put("/nonexistent/arquivo", "arquivo").await;
+
+    match resultado {
+        Err(TftpError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        _ => panic!("resultado: {:?}", resultado),
+    }
+}
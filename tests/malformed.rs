@@ -0,0 +1,44 @@
+// A malformed packet from the peer is answered with ERR 4 (Illegal TFTP operation)
+// and ends the transfer with an error, instead of bringing it down.
+
+mod common;
+
+use tokio::net::UdpSocket;
+use tftp::{ErrorCode, TftpError};
+use common::{ack, data, err, espera, servidor_falso, servidor_real, BLKSIZE};
+
+#[tokio::test]
+async fn client_answers_truncated_data_with_err4() {
+    let (sock, cliente) = servidor_falso().await;
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&data(1, &[7; BLKSIZE]), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(1));
+        // a DATA without its block number
+        sock.send_to(b"\0\x03\0", peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        err(&buf[..n])
+    };
+    let (resultado, (code, _)) = tokio::join!(cliente.get_into("arquivo", Vec::new()), servidor);
+
+    assert_eq!(code, u16::from(ErrorCode::IllegalOperation));
+    assert!(matches!(resultado, Err(TftpError::Protocol(_))), "resultado: {:?}", resultado);
+}
+
+#[tokio::test]
+async fn server_answers_bad_request_with_err4() {
+    let (dir, port) = servidor_real("malformed", b"conteudo").await;
+
+    let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = [0u8; 1024];
+    for pkt in [&b"\0\x01arquivo"[..], b"\0\x01arquivo\0binario\0", b"\0\x01arquivo\0octet\0blksize\0"] {
+        sock.send_to(pkt, ("127.0.0.1", port)).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        let (code, _) = err(&buf[..n]);
+        assert_eq!(code, u16::from(ErrorCode::IllegalOperation), "request {:?}", pkt);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
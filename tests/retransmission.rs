@@ -0,0 +1,138 @@
+// A receiver must retransmit its last packet (the RRQ before the first block, the
+// last ACK after it) when the sender goes quiet, instead of giving up at the first
+// timeout. After the last block, it must stay around long enough to acknowledge
+// it again, in case its ACK was lost.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::timeout;
+use tftp::SessionObserver;
+use common::{data, espera, servidor_falso};
+
+#[tokio::test]
+async fn client_resends_lost_rrq() {
+    let (sock, cliente) = servidor_falso().await;
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        // the first RRQ is "lost"
+        let (n, _) = espera(&sock, &mut buf).await;
+        let rrq = buf[..n].to_vec();
+        assert_eq!(&rrq[..2], &[0, 1], "expected RRQ");
+        let (n, peer) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &rrq[..], "expected the same RRQ again");
+        sock.send_to(&data(1, b"ok"), peer).await.unwrap();
+        espera(&sock, &mut buf).await;
+    };
+    let mut recebido = vec![];
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", &mut recebido), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(recebido, b"ok");
+}
+
+#[tokio::test]
+async fn client_resends_last_ack() {
+    let (sock, cliente) = servidor_falso().await;
+    let bloco: Vec<u8> = (0..512).map(|i| i as u8).collect();
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&data(1, &bloco), peer).await.unwrap();
+        // DATA 2 is only sent after ACK 1 arrives a second time, as if the first
+        // one had been lost
+        for _ in 0..2 {
+            let (n, _) = espera(&sock, &mut buf).await;
+            assert_eq!(&buf[..n], &[0, 4, 0, 1], "expected ACK 1");
+        }
+        sock.send_to(&data(2, b"fim"), peer).await.unwrap();
+        espera(&sock, &mut buf).await;
+    };
+    let mut recebido = vec![];
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", &mut recebido), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(recebido.len(), 515);
+}
+
+#[tokio::test]
+async fn client_reacks_last_block_while_dallying() {
+    let (sock, cliente) = servidor_falso().await;
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&data(1, b"fim"), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &[0, 4, 0, 1], "expected ACK 1");
+        // that ACK is "lost": the last block is retransmitted, and must be
+        // acknowledged again
+        sock.send_to(&data(1, b"fim"), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &[0, 4, 0, 1], "expected ACK 1 again");
+    };
+    let mut recebido = vec![];
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", &mut recebido), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(recebido, b"fim");
+}
+
+// records the retransmissions reported to the observer
+#[derive(Default)]
+struct Retransmissoes(Mutex<Vec<(u16, u16)>>);
+
+impl SessionObserver for Retransmissoes {
+    fn retransmitted(&self, block: u16, tentativa: u16) {
+        self.0.lock().unwrap().push((block, tentativa));
+    }
+}
+
+#[tokio::test]
+async fn observer_sees_resent_request_and_ack() {
+    let (sock, mut cliente) = servidor_falso().await;
+    let observer = Arc::new(Retransmissoes::default());
+    cliente.set_observer(observer.clone());
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        // the RRQ and then ACK 1 are "lost" once each
+        espera(&sock, &mut buf).await;
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&data(1, &[7; 512]), peer).await.unwrap();
+        for _ in 0..2 {
+            espera(&sock, &mut buf).await;
+        }
+        sock.send_to(&data(2, b"fim"), peer).await.unwrap();
+        espera(&sock, &mut buf).await;
+    };
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", Vec::new()), servidor);
+
+    assert_eq!(resultado.unwrap().retransmissoes, 2);
+    assert_eq!(*observer.0.lock().unwrap(), [(0, 1), (1, 1)]);
+}
+
+#[tokio::test]
+async fn huge_timers_do_not_overflow() {
+    let (sock, mut cliente) = servidor_falso().await;
+    assert!(cliente.set_rto_limits(Duration::MAX, Duration::MAX));
+    // lingers "forever" after the last block
+    cliente.set_dally(Duration::MAX);
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&data(1, b"fim"), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &[0, 4, 0, 1], "expected ACK 1");
+    };
+    let mut recebido = vec![];
+    let transfer = timeout(Duration::from_millis(500), cliente.get_into("arquivo", &mut recebido));
+    let (resultado, _) = tokio::join!(transfer, servidor);
+
+    assert!(resultado.is_err(), "the client should still be dallying");
+    assert_eq!(recebido, b"fim");
+}
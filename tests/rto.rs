@@ -0,0 +1,65 @@
+// Karn's algorithm (RFC 6298, section 5): the reply to a retransmitted block isn't
+// a valid RTT sample, so the backed off RTO is kept until the reply to a block sent
+// only once arrives. If it fell back to the old estimate instead, a sender whose
+// peer suddenly got slower would retransmit every block from then on.
+
+mod common;
+
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+use common::{ack, servidor_falso, BLKSIZE};
+
+const BLOCOS: u16 = 14;
+
+// receives DATA until the last block. Blocks from "lento" on are acknowledged only
+// after "atraso", and copies of a block already being acknowledged are ignored.
+// Returns the DATA blocks received, in order
+async fn recebe_com_atraso(sock: &UdpSocket, lento: u16, atraso: Duration) -> Vec<u16> {
+    let mut buf = [0u8; 1024];
+    let mut blocos = vec![];
+    let mut esperado = 1;
+    loop {
+        let (n, peer) = timeout(Duration::from_secs(5), sock.recv_from(&mut buf)).await
+            .expect("sender stalled").unwrap();
+        assert_eq!(&buf[..2], &[0, 3], "expected DATA");
+        let block = u16::from_be_bytes([buf[2], buf[3]]);
+        blocos.push(block);
+        if block != esperado {
+            continue;
+        }
+        if block >= lento {
+            tokio::time::sleep(atraso).await;
+        }
+        sock.send_to(&ack(block), peer).await.unwrap();
+        esperado += 1;
+        if n < BLKSIZE + 4 {
+            return blocos;
+        }
+    }
+}
+
+#[tokio::test]
+async fn rto_backoff_survives_rtt_jump() {
+    let (sock, mut cliente) = servidor_falso().await;
+    assert!(cliente.set_rto_limits(Duration::from_millis(20), Duration::from_secs(2)));
+    let dados: Vec<u8> = (0..(BLOCOS as usize - 1) * BLKSIZE + 100).map(|i| i as u8).collect();
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = sock.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..2], &[0, 2], "expected WRQ");
+        sock.send_to(&ack(0), peer).await.unwrap();
+        // the RTT jumps from almost nothing to well above the RTO
+        recebe_com_atraso(&sock, 4, Duration::from_millis(100)).await
+    };
+    let (resultado, blocos) = tokio::join!(cliente.put_from(&dados[..], "arquivo", None), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    // the first slow blocks are retransmitted until the RTO grows past the new RTT,
+    // and the later ones are sent only once
+    for block in 8..=BLOCOS {
+        let copias = blocos.iter().filter(|&&b| b == block).count();
+        assert_eq!(copias, 1, "block {} sent {} times: {:?}", block, copias, blocos);
+    }
+}
@@ -0,0 +1,133 @@
+// ServidorTFTP takes requests over both IPv4 and IPv6, and answers each client from
+// a session socket of the client's own family. Written files stay inside its
+// directory, are never overwritten, and are removed if the upload fails. A request
+// sent again while its session runs doesn't start another one.
+
+mod common;
+
+use std::path::Path;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tftp::{ClienteTFTP, ErrorCode};
+use common::{ack, data, err, espera, nada, servidor_real, BLKSIZE};
+
+// a request for "fname" in octet mode: RRQ if "leitura", WRQ otherwise
+fn requisicao(leitura: bool, fname: &str) -> Vec<u8> {
+    let mut pkt = vec![0, if leitura { 1 } else { 2 }];
+    pkt.extend_from_slice(fname.as_bytes());
+    pkt.extend_from_slice(b"\0octet\0");
+    pkt
+}
+
+// sends "pkt" to the server at "port", and returns the ERR code in its reply
+async fn erro_para(port: u16, pkt: &[u8]) -> u16 {
+    let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    sock.send_to(pkt, ("127.0.0.1", port)).await.unwrap();
+    let mut buf = [0u8; 1024];
+    let (n, _) = espera(&sock, &mut buf).await;
+    err(&buf[..n]).0
+}
+
+#[tokio::test]
+async fn server_answers_ipv4_and_ipv6_clients() {
+    let conteudo: Vec<u8> = (0..2 * BLKSIZE + 10).map(|i| i as u8).collect();
+    let (dir, port) = servidor_real("familias", &conteudo).await;
+    let mut servidores = vec!["127.0.0.1"];
+    // not every host has IPv6
+    if std::net::UdpSocket::bind("[::1]:0").is_ok() {
+        servidores.push("::1");
+    }
+
+    for servidor in servidores {
+        let mut cliente = ClienteTFTP::new(servidor, port);
+        cliente.set_dally(std::time::Duration::ZERO);
+        let mut recebido = vec![];
+        let resultado = cliente.get_into("arquivo", &mut recebido).await;
+        assert!(resultado.is_ok(), "{}: {:?}", servidor, resultado);
+        assert_eq!(recebido, conteudo, "{}", servidor);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn server_refuses_names_outside_its_dir() {
+    let (dir, port) = servidor_real("fora", b"conteudo").await;
+    let fora = format!("tftp-escapou-{}", std::process::id());
+    let absoluto = dir.join("absoluto");
+
+    for fname in [format!("../{}", fora), format!("sub/../../{}", fora), absoluto.display().to_string()] {
+        let code = erro_para(port, &requisicao(false, &fname)).await;
+        assert_eq!(code, u16::from(ErrorCode::AccessViolation), "WRQ {}", fname);
+    }
+    // an existing file, but named by its absolute path
+    let code = erro_para(port, &requisicao(true, &dir.join("arquivo").display().to_string())).await;
+    assert_eq!(code, u16::from(ErrorCode::AccessViolation), "RRQ by absolute path");
+
+    assert!(!dir.parent().unwrap().join(&fora).exists());
+    assert!(!absoluto.exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn server_does_not_overwrite_files() {
+    let (dir, port) = servidor_real("existe", b"conteudo").await;
+
+    let code = erro_para(port, &requisicao(false, "arquivo")).await;
+
+    assert_eq!(code, u16::from(ErrorCode::FileExists));
+    assert_eq!(std::fs::read(dir.join("arquivo")).unwrap(), b"conteudo");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+// waits a while for "path" to disappear
+async fn removido(path: &Path) -> bool {
+    for _ in 0..20 {
+        if !path.exists() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn server_removes_failed_upload() {
+    let (dir, port) = servidor_real("parcial", b"conteudo").await;
+
+    let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    sock.send_to(&requisicao(false, "parcial"), ("127.0.0.1", port)).await.unwrap();
+    let mut buf = [0u8; 1024];
+    let (n, tid) = espera(&sock, &mut buf).await;
+    assert_eq!(&buf[..n], &ack(0));
+    sock.send_to(&data(1, &[7; BLKSIZE]), tid).await.unwrap();
+    let (n, _) = espera(&sock, &mut buf).await;
+    assert_eq!(&buf[..n], &ack(1));
+    assert!(dir.join("parcial").exists());
+    // the client gives up halfway
+    sock.send_to(b"\0\x05\0\x00desisti\0", tid).await.unwrap();
+
+    assert!(removido(&dir.join("parcial")).await, "partial file was kept");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn server_ignores_duplicate_request() {
+    let (dir, port) = servidor_real("duplicada", b"conteudo").await;
+
+    let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    // as if the client had not got the first ACK 0 in time
+    for _ in 0..2 {
+        sock.send_to(&requisicao(false, "duplicada"), ("127.0.0.1", port)).await.unwrap();
+    }
+    let mut buf = [0u8; 1024];
+    let (n, tid) = espera(&sock, &mut buf).await;
+    assert_eq!(&buf[..n], &ack(0));
+    // no second session answers, e.g. with ERR 6 from another TID
+    nada(&sock).await;
+    sock.send_to(&data(1, b"fim"), tid).await.unwrap();
+    let (n, _) = espera(&sock, &mut buf).await;
+    assert_eq!(&buf[..n], &ack(1));
+
+    assert_eq!(std::fs::read(dir.join("duplicada")).unwrap(), b"fim");
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,118 @@
+// Regression tests for the Sorcerer's Apprentice problem (RFC 1123, 4.2.3.1):
+// a sender must retransmit DATA only when its timer expires, never because a
+// duplicate ACK arrived. Otherwise, once a block is retransmitted, every following
+// block is sent twice.
+
+mod common;
+
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+use common::{ack, servidor_falso, servidor_real, BLKSIZE};
+
+// 10 full blocks and a last partial one
+fn conteudo() -> Vec<u8> {
+    (0..10 * BLKSIZE + 100).map(|i| i as u8).collect()
+}
+
+// receives DATA from "peer" until the last block, acknowledging each one (duplicates
+// included) twice. The ACK of block "atrasado" is delayed beyond the sender's timeout,
+// so that block is retransmitted, possibly more than once, as the timeout backs off.
+// Returns the DATA blocks received, in order, including any that arrive shortly after
+// the last one
+async fn recebe_com_acks_duplicados(sock: &UdpSocket, atrasado: u16) -> Vec<u16> {
+    let mut buf = [0u8; 1024];
+    let mut blocos = vec![];
+    let mut atrasou = false;
+    loop {
+        let (n, peer) = timeout(Duration::from_secs(5), sock.recv_from(&mut buf)).await
+            .expect("sender stalled").unwrap();
+        assert_eq!(&buf[..2], &[0, 3], "expected DATA");
+        let block = u16::from_be_bytes([buf[2], buf[3]]);
+        blocos.push(block);
+        if block == atrasado && ! atrasou {
+            atrasou = true;
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        sock.send_to(&ack(block), peer).await.unwrap();
+        sock.send_to(&ack(block), peer).await.unwrap();
+        if n < BLKSIZE + 4 {
+            break;
+        }
+    }
+    // a sender with the bug would still be sending duplicates
+    while let Ok(Ok(_)) = timeout(Duration::from_millis(500), sock.recv_from(&mut buf)).await {
+        blocos.push(u16::from_be_bytes([buf[2], buf[3]]));
+    }
+    blocos
+}
+
+// "atrasado" was retransmitted, but every other block was sent only once, in order
+fn confere(blocos: &[u16], atrasado: u16) {
+    let outros: Vec<u16> = blocos.iter().copied().filter(|&b| b != atrasado).collect();
+    let esperados: Vec<u16> = (1..=11).filter(|&b| b != atrasado).collect();
+    assert_eq!(outros, esperados, "blocks: {:?}", blocos);
+    assert!(blocos.iter().filter(|&&b| b == atrasado).count() > 1, "blocks: {:?}", blocos);
+}
+
+#[tokio::test]
+async fn client_put_ignores_duplicate_acks() {
+    let (sock, cliente) = servidor_falso().await;
+    let dados = conteudo();
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = sock.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..2], &[0, 2], "expected WRQ");
+        sock.send_to(&ack(0), peer).await.unwrap();
+        recebe_com_acks_duplicados(&sock, 3).await
+    };
+    let (resultado, blocos) = tokio::join!(cliente.put_from(&dados[..], "arquivo", None), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    confere(&blocos, 3);
+}
+
+#[tokio::test]
+async fn server_rrq_ignores_duplicate_acks() {
+    let (dir, port) = servidor_real("sa", &conteudo()).await;
+
+    let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    sock.send_to(b"\0\x01arquivo\0octet\0", ("127.0.0.1", port)).await.unwrap();
+    let blocos = recebe_com_acks_duplicados(&sock, 5).await;
+
+    confere(&blocos, 5);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn duplicate_acks_do_not_hold_back_retransmission() {
+    let (sock, cliente) = servidor_falso().await;
+    let dados = conteudo();
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = sock.recv_from(&mut buf).await.unwrap();
+        sock.send_to(&ack(0), peer).await.unwrap();
+        // DATA 1 is "lost": only duplicates of ACK 0 are sent, faster than the
+        // sender's timeout. DATA 1 must still be retransmitted when it expires
+        let (_, peer) = sock.recv_from(&mut buf).await.unwrap();
+        assert_eq!(u16::from_be_bytes([buf[2], buf[3]]), 1);
+        let duplicados = async {
+            loop {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                sock.send_to(&ack(0), peer).await.unwrap();
+            }
+        };
+        tokio::select! {
+            r = sock.recv_from(&mut buf) => assert!(r.is_ok()),
+            _ = duplicados => unreachable!(),
+            _ = tokio::time::sleep(Duration::from_secs(3)) => panic!("DATA 1 was not retransmitted"),
+        }
+        u16::from_be_bytes([buf[2], buf[3]])
+    };
+    let transfer = timeout(Duration::from_secs(3), cliente.put_from(&dados[..], "arquivo", None));
+    let (_, block) = tokio::join!(transfer, servidor);
+
+    assert_eq!(block, 1);
+}
@@ -0,0 +1,112 @@
+// Transfer IDs (RFC 1350, section 4): once the peer's TID is known, packets from
+// any other port are answered with ERR 5 (Unknown transfer ID) and don't disturb
+// the transfer. Packets from other hosts are ignored.
+
+mod common;
+
+use tokio::net::UdpSocket;
+use common::{ack, data, espera, nada, servidor_falso, servidor_real, BLKSIZE};
+
+fn unknown_tid(pkt: &[u8]) -> bool {
+    pkt.len() > 4 && pkt[..4] == [0, 5, 0, 5]
+}
+
+#[tokio::test]
+async fn client_answers_foreign_tid_with_err5() {
+    let (sock, cliente) = servidor_falso().await;
+    let bloco = vec![7u8; BLKSIZE];
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        // the server's TID
+        let tid = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        tid.send_to(&data(1, &bloco), peer).await.unwrap();
+        let (n, _) = espera(&tid, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(1));
+
+        // another port on the same host
+        let intruso = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        intruso.send_to(&data(2, b"intruso"), peer).await.unwrap();
+        let (n, _) = espera(&intruso, &mut buf).await;
+        assert!(unknown_tid(&buf[..n]), "expected ERR 5, got {:?}", &buf[..n]);
+
+        // the transfer goes on, though ACK 1 may have been retransmitted meanwhile
+        tid.send_to(&data(2, b"fim"), peer).await.unwrap();
+        loop {
+            let (n, _) = espera(&tid, &mut buf).await;
+            if buf[..n] == ack(2) {
+                break;
+            }
+            assert_eq!(&buf[..n], &ack(1));
+        }
+    };
+    let mut recebido = vec![];
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", &mut recebido), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(recebido.len(), BLKSIZE + 3);
+    assert_eq!(&recebido[BLKSIZE..], b"fim");
+}
+
+// the whole 127.0.0.0/8 block is bound to the loopback interface only on Linux
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn client_ignores_replies_from_other_hosts() {
+    let (sock, cliente) = servidor_falso().await;
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        // a spoofed reply, from another address, arrives before the server's
+        let falso = UdpSocket::bind("127.0.0.2:0").await.unwrap();
+        falso.send_to(&data(1, b"falso"), peer).await.unwrap();
+        nada(&falso).await;
+
+        let tid = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        tid.send_to(&data(1, b"ok"), peer).await.unwrap();
+        let (n, _) = espera(&tid, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(1));
+    };
+    let mut recebido = vec![];
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", &mut recebido), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(recebido, b"ok");
+}
+
+#[tokio::test]
+async fn server_answers_foreign_tid_with_err5() {
+    let conteudo: Vec<u8> = (0..2 * BLKSIZE + 10).map(|i| i as u8).collect();
+    let (dir, port) = servidor_real("tid", &conteudo).await;
+
+    let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    sock.send_to(b"\0\x01arquivo\0octet\0", ("127.0.0.1", port)).await.unwrap();
+    let mut buf = [0u8; 1024];
+    let (_, tid) = espera(&sock, &mut buf).await;
+    assert_eq!(&buf[..4], &[0, 3, 0, 1], "expected DATA 1");
+
+    // an ACK from another port is refused, and the transfer goes on
+    let intruso = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    intruso.send_to(&ack(1), tid).await.unwrap();
+    let (n, _) = espera(&intruso, &mut buf).await;
+    assert!(unknown_tid(&buf[..n]), "expected ERR 5, got {:?}", &buf[..n]);
+    nada(&sock).await;
+
+    let mut recebido = vec![];
+    let mut block = 1;
+    loop {
+        sock.send_to(&ack(block), tid).await.unwrap();
+        let (n, addr) = espera(&sock, &mut buf).await;
+        assert_eq!(addr, tid);
+        block += 1;
+        assert_eq!(&buf[..4], &[0, 3, 0, block as u8], "expected DATA {}", block);
+        recebido.extend_from_slice(&buf[4..n]);
+        if n < BLKSIZE + 4 {
+            sock.send_to(&ack(block), tid).await.unwrap();
+            break;
+        }
+    }
+    assert_eq!(recebido, &conteudo[BLKSIZE..]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,96 @@
+// Timeout interval option (RFC 2349): the server must acknowledge exactly the
+// requested timeout, and only values in 1..=255 are accepted. A receiver lingers
+// after the last block for longer than the timeout, so it can acknowledge that block
+// again when the sender retransmits it.
+
+mod common;
+
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tftp::{ErrorCode, TftpError};
+use common::{ack, data, err, espera, oack, opcoes, servidor_falso, servidor_real};
+
+#[tokio::test]
+async fn client_rejects_different_timeout() {
+    let (sock, mut cliente) = servidor_falso().await;
+    assert!(cliente.set_timeout(3));
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&oack(&[("timeout", "5")]), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        err(&buf[..n])
+    };
+    let (resultado, (code, _)) = tokio::join!(cliente.get_into("arquivo", Vec::new()), servidor);
+
+    assert_eq!(code, u16::from(ErrorCode::OptionRejected));
+    assert!(matches!(resultado, Err(TftpError::Negotiation(_))), "resultado: {:?}", resultado);
+}
+
+#[tokio::test]
+async fn server_refuses_timeout_out_of_range() {
+    let (dir, port) = servidor_real("timeout", b"conteudo").await;
+
+    for timeout in ["0", "256"] {
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut rrq = b"\0\x01arquivo\0octet\0timeout\0".to_vec();
+        rrq.extend_from_slice(timeout.as_bytes());
+        rrq.extend_from_slice(b"\0tsize\0");
+        rrq.extend_from_slice(b"0\0");
+        sock.send_to(&rrq, ("127.0.0.1", port)).await.unwrap();
+        let mut buf = [0u8; 1024];
+        let (n, tid) = espera(&sock, &mut buf).await;
+        // the other option is still acknowledged, without the timeout
+        assert_eq!(&buf[..2], &[0, 6], "timeout {}: expected OACK", timeout);
+        assert_eq!(opcoes(&buf[..n]), [("tsize".to_string(), "8".to_string())], "timeout {}", timeout);
+        sock.send_to(b"\0\x05\0\x00desisti\0", tid).await.unwrap();
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn client_dallies_longer_than_the_timeout() {
+    let (sock, mut cliente) = servidor_falso().await;
+    assert!(cliente.set_timeout(2));
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&oack(&[("timeout", "2")]), peer).await.unwrap();
+        espera(&sock, &mut buf).await;
+        sock.send_to(&data(1, b"fim"), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(1));
+        // ACK 1 is "lost": the server retransmits only after its timeout
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        sock.send_to(&data(1, b"fim"), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(1), "expected ACK 1 again");
+    };
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", Vec::new()), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+}
+
+#[tokio::test]
+async fn server_dallies_longer_than_the_timeout() {
+    let (dir, port) = servidor_real("dally", b"conteudo").await;
+
+    let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut wrq = b"\0\x02novo\0octet\0timeout\0".to_vec();
+    wrq.extend_from_slice(b"2\0");
+    sock.send_to(&wrq, ("127.0.0.1", port)).await.unwrap();
+    let mut buf = [0u8; 1024];
+    let (n, tid) = espera(&sock, &mut buf).await;
+    assert_eq!(&buf[..n], &oack(&[("timeout", "2")]));
+    sock.send_to(&data(1, b"fim"), tid).await.unwrap();
+    let (n, _) = espera(&sock, &mut buf).await;
+    assert_eq!(&buf[..n], &ack(1));
+    // ACK 1 is "lost", and the client retransmits after the negotiated timeout
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    sock.send_to(&data(1, b"fim"), tid).await.unwrap();
+    let (n, _) = espera(&sock, &mut buf).await;
+    assert_eq!(&buf[..n], &ack(1), "expected ACK 1 again");
+    let _ = std::fs::remove_dir_all(&dir);
+}
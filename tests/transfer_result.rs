@@ -0,0 +1,199 @@
+// Transfers return the statistics of a successful transfer, or a TftpError saying
+// why it failed. The observer is told the same outcome, once, even when the
+// transfer fails before it starts or while the local file is replaced.
+
+mod common;
+
+use std::error::Error;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tftp::{ClienteTFTP, ErrorCode, SessionObserver, TftpError, TransferStats};
+use common::{data, dir_local, espera, servidor_falso, BLKSIZE};
+
+#[tokio::test]
+async fn stats_of_a_successful_transfer() {
+    let (sock, cliente) = servidor_falso().await;
+    let dados: Vec<u8> = (0..512 + 100).map(|i| i as u8).collect();
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(b"\0\x04\0\0", peer).await.unwrap();
+        for block in 1..=2u8 {
+            espera(&sock, &mut buf).await;
+            sock.send_to(&[0, 4, 0, block], peer).await.unwrap();
+        }
+    };
+    let (resultado, _) = tokio::join!(cliente.put_from(&dados[..], "arquivo", None), servidor);
+
+    let stats = resultado.unwrap();
+    assert_eq!(stats.blocos, 2);
+    assert_eq!(stats.bytes, dados.len() as u64);
+    assert_eq!(stats.retransmissoes, 0);
+}
+
+#[tokio::test]
+async fn err_from_the_server() {
+    let (sock, cliente) = servidor_falso().await;
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(b"\0\x05\0\x01nao existe\0", peer).await.unwrap();
+    };
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", Vec::new()), servidor);
+
+    match resultado {
+        Err(TftpError::Peer(code, texto)) => {
+            assert_eq!(code, ErrorCode::FileNotFound);
+            assert_eq!(texto, "nao existe");
+        }
+        _ => panic!("resultado: {:?}", resultado),
+    }
+}
+
+#[tokio::test]
+async fn unknown_server() {
+    let cliente = ClienteTFTP::new("servidor.invalid", 69);
+    let resultado = cliente.get_into("arquivo", Vec::new()).await;
+
+    assert!(matches!(resultado, Err(TftpError::Resolution(_))), "resultado: {:?}", resultado);
+}
+
+#[test]
+fn io_errors_keep_their_source() {
+    let erro = TftpError::from(io::Error::from(io::ErrorKind::StorageFull));
+
+    let fonte = erro.source().and_then(|e| e.downcast_ref::<io::Error>()).unwrap();
+    assert_eq!(fonte.kind(), io::ErrorKind::StorageFull);
+    assert!(erro.to_string().starts_with("local I/O error"));
+}
+
+#[tokio::test]
+async fn failed_get_keeps_the_local_file() {
+    let (sock, cliente) = servidor_falso().await;
+    let dir = dir_local("get-falha", b"anterior");
+    let local = dir.join("arquivo");
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&data(1, &[7; BLKSIZE]), peer).await.unwrap();
+        espera(&sock, &mut buf).await;
+        sock.send_to(b"\0\x05\0\x03disco cheio\0", peer).await.unwrap();
+    };
+    let (resultado, _) = tokio::join!(cliente.get("arquivo", local.to_str().unwrap()), servidor);
+
+    assert!(matches!(resultado, Err(TftpError::Peer(ErrorCode::DiskFull, _))), "resultado: {:?}", resultado);
+    assert_eq!(std::fs::read(&local).unwrap(), b"anterior");
+    // nor is the partial download left behind
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn get_replaces_the_local_file() {
+    let (sock, cliente) = servidor_falso().await;
+    let dir = dir_local("get-ok", b"anterior");
+    let local = dir.join("arquivo");
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&data(1, b"novo"), peer).await.unwrap();
+        espera(&sock, &mut buf).await;
+    };
+    let (resultado, _) = tokio::join!(cliente.get("arquivo", local.to_str().unwrap()), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(std::fs::read(&local).unwrap(), b"novo");
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+// records the outcomes reported to the observer: the blocks transferred, or the error
+#[derive(Default)]
+struct Resultados(Mutex<Vec<Result<u64, String>>>);
+
+impl SessionObserver for Resultados {
+    fn finished(&self, resultado: Result<&TransferStats, &TftpError>) {
+        self.0.lock().unwrap().push(resultado.map(|stats| stats.blocos).map_err(|e| format!("{:?}", e)));
+    }
+}
+
+// a client with a Resultados observer
+fn observado(cliente: &mut ClienteTFTP) -> Arc<Resultados> {
+    let observer = Arc::new(Resultados::default());
+    cliente.set_observer(observer.clone());
+    observer
+}
+
+#[tokio::test]
+async fn observer_sees_a_successful_transfer_once() {
+    let (sock, mut cliente) = servidor_falso().await;
+    let observer = observado(&mut cliente);
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&data(1, b"fim"), peer).await.unwrap();
+        espera(&sock, &mut buf).await;
+    };
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", Vec::new()), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(*observer.0.lock().unwrap(), [Ok(1)]);
+}
+
+#[tokio::test]
+async fn observer_sees_unknown_server() {
+    let mut cliente = ClienteTFTP::new("servidor.invalid", 69);
+    let observer = observado(&mut cliente);
+
+    let resultado = cliente.put_from(&b"dados"[..], "arquivo", None).await;
+
+    assert!(resultado.is_err());
+    let resultados = observer.0.lock().unwrap();
+    assert!(matches!(&resultados[..], [Err(e)] if e.starts_with("Resolution")), "observer: {:?}", resultados);
+}
+
+#[tokio::test]
+async fn observer_sees_local_errors() {
+    let (_sock, mut cliente) = servidor_falso().await;
+    let observer = observado(&mut cliente);
+    let ausente = std::env::temp_dir().join(format!("tftp-ausente-{}", std::process::id()));
+    let ausente = ausente.join("arquivo");
+    let ausente = ausente.to_str().unwrap();
+
+    // neither the file to be sent nor the temporary file of the download can be opened
+    assert!(cliente.put(ausente, "arquivo").await.is_err());
+    assert!(cliente.get("arquivo", ausente).await.is_err());
+
+    let resultados = observer.0.lock().unwrap();
+    assert!(matches!(&resultados[..], [Err(a), Err(b)] if a.starts_with("Io") && b.starts_with("Io")),
+        "observer: {:?}", resultados);
+}
+
+#[tokio::test]
+async fn observer_sees_failure_to_replace_the_local_file() {
+    let (sock, mut cliente) = servidor_falso().await;
+    let observer = observado(&mut cliente);
+    let dir = dir_local("get-rename", b"anterior");
+    // a directory that isn't empty can't be replaced by the download
+    let local = dir.join("sub");
+    std::fs::create_dir(&local).unwrap();
+    std::fs::write(local.join("arquivo"), b"anterior").unwrap();
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&data(1, b"novo"), peer).await.unwrap();
+        espera(&sock, &mut buf).await;
+    };
+    let (resultado, _) = tokio::join!(cliente.get("arquivo", local.to_str().unwrap()), servidor);
+
+    assert!(matches!(resultado, Err(TftpError::Io(_))), "resultado: {:?}", resultado);
+    let resultados = observer.0.lock().unwrap();
+    assert!(matches!(&resultados[..], [Err(e)] if e.starts_with("Io")), "observer: {:?}", resultados);
+    let _ = std::fs::remove_dir_all(&dir);
+}
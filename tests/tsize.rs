@@ -0,0 +1,79 @@
+// Transfer size option (RFC 2349): a RRQ asks for the file size, which is handed to
+// the on_tsize hook before any DATA arrives, and a WRQ reports the size of what is
+// about to be sent.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use tftp::{ErrorCode, TftpError};
+use common::{ack, data, err, espera, nada, oack, opcoes, servidor_falso, BLKSIZE};
+
+#[tokio::test]
+async fn client_get_hands_tsize_to_hook() {
+    let (sock, mut cliente) = servidor_falso().await;
+    let tamanho = Arc::new(Mutex::new(None));
+    let t = tamanho.clone();
+    cliente.on_tsize(move |tsize| {
+        *t.lock().unwrap() = Some(tsize);
+        true
+    });
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (n, peer) = espera(&sock, &mut buf).await;
+        assert!(opcoes(&buf[..n]).contains(&("tsize".to_string(), "0".to_string())), "request: {:?}", &buf[..n]);
+        sock.send_to(&oack(&[("tsize", "12345")]), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(0));
+        sock.send_to(&data(1, b"fim"), peer).await.unwrap();
+        espera(&sock, &mut buf).await;
+    };
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", Vec::new()), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(*tamanho.lock().unwrap(), Some(12345));
+}
+
+#[tokio::test]
+async fn client_get_aborts_when_hook_refuses_tsize() {
+    let (sock, mut cliente) = servidor_falso().await;
+    cliente.on_tsize(|tsize| tsize < 1000);
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&oack(&[("tsize", "12345")]), peer).await.unwrap();
+        // an ERR instead of ACK 0, so no DATA is ever sent
+        let (n, _) = espera(&sock, &mut buf).await;
+        let erro = err(&buf[..n]);
+        nada(&sock).await;
+        erro
+    };
+    let (resultado, (code, _)) = tokio::join!(cliente.get_into("arquivo", Vec::new()), servidor);
+
+    assert_eq!(code, u16::from(ErrorCode::DiskFull));
+    assert!(matches!(resultado, Err(TftpError::Negotiation(_))), "resultado: {:?}", resultado);
+}
+
+#[tokio::test]
+async fn client_put_sends_tsize() {
+    let (sock, cliente) = servidor_falso().await;
+    let conteudo = vec![7u8; BLKSIZE + 100];
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (n, peer) = espera(&sock, &mut buf).await;
+        let pedidas = opcoes(&buf[..n]);
+        sock.send_to(&oack(&[("tsize", &conteudo.len().to_string())]), peer).await.unwrap();
+        for b in 1..=2 {
+            espera(&sock, &mut buf).await;
+            sock.send_to(&ack(b), peer).await.unwrap();
+        }
+        pedidas
+    };
+    let (resultado, pedidas) = tokio::join!(
+        cliente.put_from(&conteudo[..], "arquivo", Some(conteudo.len() as u64)), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(pedidas, [("tsize".to_string(), conteudo.len().to_string())]);
+}
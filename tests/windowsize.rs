@@ -0,0 +1,185 @@
+// Windowed transfers (RFC 7440): the receiver acknowledges only the last block of
+// each window, or the last block received in order when one is missing, and the
+// sender goes back to the first block not acknowledged. A window whose ACK is
+// lost is sent again as a whole. The server agrees to 64 blocks at most.
+
+mod common;
+
+use tokio::net::UdpSocket;
+use common::{ack, block, data, espera, servidor_falso, servidor_real, BLKSIZE};
+
+// 6 full blocks and a last partial one
+fn conteudo() -> Vec<u8> {
+    (0..6 * BLKSIZE + 100).map(|i| i as u8).collect()
+}
+
+// the contents of block "block"
+fn bloco(conteudo: &[u8], block: u16) -> &[u8] {
+    let inicio = (block as usize - 1) * BLKSIZE;
+    &conteudo[inicio..conteudo.len().min(inicio + BLKSIZE)]
+}
+
+fn oack(windowsize: u16) -> Vec<u8> {
+    let mut pkt = b"\0\x06windowsize\0".to_vec();
+    pkt.extend_from_slice(windowsize.to_string().as_bytes());
+    pkt.push(0);
+    pkt
+}
+
+#[tokio::test]
+async fn client_get_acks_last_block_before_a_gap() {
+    let (sock, mut cliente) = servidor_falso().await;
+    assert!(cliente.set_windowsize(4));
+    let conteudo = conteudo();
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&oack(4), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(0));
+        // DATA 3 is "lost"
+        for b in [1, 2, 4] {
+            sock.send_to(&data(b, bloco(&conteudo, b)), peer).await.unwrap();
+        }
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(2), "expected ACK 2");
+        // the next window starts at the missing block
+        for b in 3..=6 {
+            sock.send_to(&data(b, bloco(&conteudo, b)), peer).await.unwrap();
+        }
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(6), "expected ACK 6");
+        sock.send_to(&data(7, bloco(&conteudo, 7)), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(7), "expected ACK 7");
+    };
+    let mut recebido = vec![];
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", &mut recebido), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(recebido, conteudo);
+}
+
+#[tokio::test]
+async fn client_get_ignores_duplicates_inside_a_window() {
+    let (sock, mut cliente) = servidor_falso().await;
+    assert!(cliente.set_windowsize(4));
+    let conteudo = conteudo();
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&oack(4), peer).await.unwrap();
+        espera(&sock, &mut buf).await;
+        // DATA 2 arrives twice: the copy is neither stored nor acknowledged, so the
+        // window still ends at block 4
+        for b in [1, 2, 2, 3, 4] {
+            sock.send_to(&data(b, bloco(&conteudo, b)), peer).await.unwrap();
+        }
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(4), "expected ACK 4");
+        for b in [5, 6, 6, 7] {
+            sock.send_to(&data(b, bloco(&conteudo, b)), peer).await.unwrap();
+        }
+        let (n, _) = espera(&sock, &mut buf).await;
+        assert_eq!(&buf[..n], &ack(7), "expected ACK 7");
+    };
+    let mut recebido = vec![];
+    let (resultado, _) = tokio::join!(cliente.get_into("arquivo", &mut recebido), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(recebido, conteudo);
+}
+
+#[tokio::test]
+async fn client_put_goes_back_to_the_gap() {
+    let (sock, mut cliente) = servidor_falso().await;
+    assert!(cliente.set_windowsize(4));
+    let conteudo = conteudo();
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let mut blocos = vec![];
+        let mut recebido = vec![];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&oack(4), peer).await.unwrap();
+        // blocks 3 and 4 of the first window are "lost": only 1 and 2 are acknowledged
+        for _ in 0..4 {
+            let (n, _) = espera(&sock, &mut buf).await;
+            blocos.push(block(&buf));
+            if block(&buf) <= 2 {
+                recebido.extend_from_slice(&buf[4..n]);
+            }
+        }
+        sock.send_to(&ack(2), peer).await.unwrap();
+        for _ in 0..4 {
+            let (n, _) = espera(&sock, &mut buf).await;
+            blocos.push(block(&buf));
+            recebido.extend_from_slice(&buf[4..n]);
+        }
+        sock.send_to(&ack(6), peer).await.unwrap();
+        let (n, _) = espera(&sock, &mut buf).await;
+        blocos.push(block(&buf));
+        recebido.extend_from_slice(&buf[4..n]);
+        sock.send_to(&ack(7), peer).await.unwrap();
+        (blocos, recebido)
+    };
+    let (resultado, (blocos, recebido)) = tokio::join!(
+        cliente.put_from(&conteudo[..], "arquivo", None), servidor);
+
+    assert!(resultado.is_ok(), "resultado: {:?}", resultado);
+    assert_eq!(blocos, [1, 2, 3, 4, 3, 4, 5, 6, 7]);
+    assert_eq!(recebido, conteudo);
+}
+
+#[tokio::test]
+async fn client_put_resends_window_when_its_ack_is_lost() {
+    let (sock, mut cliente) = servidor_falso().await;
+    assert!(cliente.set_windowsize(4));
+    let conteudo = conteudo();
+
+    let servidor = async {
+        let mut buf = [0u8; 1024];
+        let mut blocos = vec![];
+        let (_, peer) = espera(&sock, &mut buf).await;
+        sock.send_to(&oack(4), peer).await.unwrap();
+        // the first window arrives, but its ACK is "lost"
+        for _ in 0..4 {
+            espera(&sock, &mut buf).await;
+            blocos.push(block(&buf));
+        }
+        // the whole window is sent again when the sender's timer expires
+        for _ in 0..4 {
+            espera(&sock, &mut buf).await;
+            blocos.push(block(&buf));
+        }
+        sock.send_to(&ack(4), peer).await.unwrap();
+        for _ in 0..3 {
+            espera(&sock, &mut buf).await;
+            blocos.push(block(&buf));
+        }
+        sock.send_to(&ack(7), peer).await.unwrap();
+        blocos
+    };
+    let (resultado, blocos) = tokio::join!(cliente.put_from(&conteudo[..], "arquivo", None), servidor);
+
+    let stats = resultado.unwrap();
+    assert_eq!(blocos, [1, 2, 3, 4, 1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(stats.blocos, 7);
+    assert_eq!(stats.retransmissoes, 1);
+}
+
+#[tokio::test]
+async fn server_caps_windowsize() {
+    let (dir, port) = servidor_real("janela", b"conteudo").await;
+
+    let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    sock.send_to(b"\0\x01arquivo\0octet\0windowsize\x0065535\0", ("127.0.0.1", port)).await.unwrap();
+    let mut buf = [0u8; 1024];
+    let (n, tid) = espera(&sock, &mut buf).await;
+
+    assert_eq!(&buf[..n], &oack(64));
+    sock.send_to(b"\0\x05\0\x00desisti\0", tid).await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}